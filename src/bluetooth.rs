@@ -1,14 +1,17 @@
 extern crate btleplug;
 
-use btleplug::api::{Central, Characteristic, Peripheral, WriteType};
+use btleplug::api::{BDAddr, Central, CentralEvent, CharPropFlags, Characteristic, Peripheral, WriteType};
 use std::thread;
-use std::time::{Duration, SystemTime};
-use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::io;
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
 use btleplug::{Result, Error};
 
 #[cfg(target_os = "linux")]
-use btleplug::bluez::{adapter::ConnectedAdapter, manager::Manager};
+use btleplug::bluez::{adapter::Adapter, manager::Manager};
 #[cfg(target_os = "macos")]
 use btleplug::corebluetooth::{adapter::Adapter, manager::Manager};
 #[cfg(target_os = "windows")]
@@ -20,37 +23,185 @@ pub const SECRET_KEY: &str = "1002000b-2749-0001-0000-00805f9b042f";
 pub const DEVICE_NAME: &str = "10020006-2749-0001-0000-00805f9b042f";
 pub const TEMPERATURE: &str = "10020005-2749-0001-0000-00805f9b042f";
 pub const SETTINGS: &str = "10020003-2749-0001-0000-00805f9b042f";
+// Current time and the offset of the time zone from UTC
+pub const TIME: &str = "10020008-2749-0001-0000-00805f9b042f";
 // Home temperature, Out temperature, Schedule Monday + Tuesday + Wednesday
 pub const SCHEDULE_1: &str = "1002000d-2749-0001-0000-00805f9b042f";
 // Schedule Thursday + Friday
 pub const SCHEDULE_2: &str = "1002000e-2749-0001-0000-00805f9b042f";
 // Schedule Saturday + Sunday
 pub const SCHEDULE_3: &str = "1002000f-2749-0001-0000-00805f9b042f";
+// Device Information service
+pub const MANUFACTURER_NAME: &str = "00002a29-0000-1000-8000-00805f9b34fb";
+pub const MODEL_NUMBER: &str = "00002a24-0000-1000-8000-00805f9b34fb";
+pub const FIRMWARE_REVISION: &str = "00002a26-0000-1000-8000-00805f9b34fb";
+pub const HARDWARE_REVISION: &str = "00002a27-0000-1000-8000-00805f9b34fb";
+pub const SOFTWARE_REVISION: &str = "00002a28-0000-1000-8000-00805f9b34fb";
 
-//
-// btleplug's interface varies a bit between OSes. According to the crate docs,
-// this will be fixed in the future.
-//
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(120);
+// Only used on platforms that don't give us an event receiver
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
 
-#[cfg(any(target_os = "windows", target_os = "macos"))]
-fn get_central(manager: &Manager) -> Adapter {
-    let adapters = manager.adapters().unwrap();
-    adapters.into_iter().nth(0).unwrap()
+/// Decides how hard we try when talking to a peripheral over a flaky link.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// The number of times an operation is attempted before giving up
+    pub attempts: u32,
+    /// The delay before the first retry. The delay doubles for each retry.
+    pub initial_backoff: Duration,
+    /// Whether to reconnect before retrying if the connection has been lost
+    pub reconnect: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            initial_backoff: Duration::from_secs(1),
+            reconnect: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Runs `operation` until it succeeds or we run out of attempts, calling
+    /// `before_retry` before each new attempt. Errors that will not go away
+    /// by trying again are returned right away.
+    fn run<T, F, G>(&self, mut operation: F, mut before_retry: G) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+        G: FnMut(),
+    {
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            match operation() {
+                Ok(result) => return Ok(result),
+                Err(error @ Error::NotSupported(_)) | Err(error @ Error::PermissionDenied) => return Err(error),
+                Err(error) if attempt >= self.attempts => return Err(error),
+                Err(error) => {
+                    eprintln!("Bluetooth operation failed: {}. Retrying in {} ms (attempt {} of {}).", error, backoff.as_millis(), attempt + 1, self.attempts);
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                    before_retry();
+                }
+            }
+        }
+    }
+}
+
+/// Picks one of the Bluetooth adapters of the computer. Given on the command
+/// line or in the config as an index, an address, or a name.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AdapterSelector {
+    Index(usize),
+    Address(BDAddr),
+    Name(String),
+}
+
+impl AdapterSelector {
+    pub fn parse(value: &str) -> Self {
+        if let Ok(index) = value.parse::<usize>() {
+            return Self::Index(index);
+        }
+        match value.parse::<BDAddr>() {
+            Ok(address) => Self::Address(address),
+            Err(_) => Self::Name(value.to_string()),
+        }
+    }
+
+    pub fn matches(&self, adapter: &AdapterInfo) -> bool {
+        match self {
+            Self::Index(index) => adapter.index == *index,
+            Self::Address(address) => adapter.address == Some(*address),
+            Self::Name(name) => adapter.id.as_ref() == Some(name) || adapter.name.as_ref() == Some(name),
+        }
+    }
+}
+
+impl fmt::Display for AdapterSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "{}", index),
+            Self::Address(address) => write!(f, "{}", address),
+            Self::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// What we know about a Bluetooth adapter. Only BlueZ tells us more than the
+/// index of the adapter.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AdapterInfo {
+    pub index: usize,
+    /// The system name of the adapter, e.g. hci0
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub address: Option<BDAddr>,
+    pub powered: Option<bool>,
 }
 
 #[cfg(target_os = "linux")]
-fn get_central(manager: &Manager) -> ConnectedAdapter {
-    let adapters = manager.adapters().unwrap();
-    let adapter = adapters.into_iter().nth(0).unwrap();
-    adapter.connect().unwrap()
+fn describe_adapter(adapter: &Adapter) -> AdapterInfo {
+    let path = adapter.proxy().path.to_string();
+    AdapterInfo {
+        index: 0,
+        id: path.rsplit('/').next().map(|id| id.to_string()),
+        name: adapter.name().ok(),
+        address: adapter.address().ok(),
+        powered: adapter.is_powered().ok(),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn describe_adapter(_adapter: &Adapter) -> AdapterInfo {
+    AdapterInfo::default()
+}
+
+fn get_adapters(manager: &Manager) -> Result<Vec<(AdapterInfo, Adapter)>> {
+    let mut adapters: Vec<(AdapterInfo, Adapter)> = manager.adapters()?.into_iter()
+        .map(|adapter| (describe_adapter(&adapter), adapter))
+        .collect();
+    // BlueZ doesn't list the adapters in any particular order, so we sort them
+    // to keep the indices stable
+    adapters.sort_by(|(info1, _), (info2, _)| info1.id.cmp(&info2.id));
+    for (index, (info, _)) in adapters.iter_mut().enumerate() {
+        info.index = index;
+    }
+    Ok(adapters)
 }
 
+fn get_central(manager: &Manager, adapter: Option<&AdapterSelector>) -> Result<Adapter> {
+    let mut adapters = get_adapters(manager)?.into_iter();
+    let central = match adapter {
+        None => adapters.next(),
+        Some(selector) => adapters.find(|(info, _)| selector.matches(info)),
+    };
+    match (central, adapter) {
+        (Some((_, central)), _) => Ok(central),
+        (None, None) => Err(Error::Other("No Bluetooth adapters found".to_string())),
+        (None, Some(selector)) => Err(Error::Other(format!("No Bluetooth adapter matches {}", selector))),
+    }
+}
+
+/// Lists the Bluetooth adapters of the computer in the order used by
+/// `AdapterSelector::Index`.
+pub fn adapters() -> Result<Vec<AdapterInfo>> {
+    let manager = Manager::new()?;
+    Ok(get_adapters(&manager)?.into_iter().map(|(info, _)| info).collect())
+}
+
+#[derive(Clone, Debug)]
 pub struct ScannedBluetoothPeripheral {
     pub name: String,
     pub address: String,
+    pub rssi: Option<i8>,
 }
 
 trait PeripheralWrapper: Send + Sync + std::fmt::Debug {
+    fn is_connected(&self) -> bool;
+    fn connect(&self) -> Result<()>;
     fn disconnect(&self) -> Result<()>;
     fn discover_characteristics(&self) -> Result<Vec<Characteristic>>;
     fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>>;
@@ -80,6 +231,14 @@ impl<P> PeripheralWrapper for BtleplugPeripheralWrapper<P>
 where
     P: Peripheral,
 {
+    fn is_connected(&self) -> bool {
+        self.p.is_connected()
+    }
+
+    fn connect(&self) -> Result<()> {
+        self.p.connect()
+    }
+
     fn disconnect(&self) -> Result<()> {
         self.p.disconnect()
     }
@@ -93,162 +252,787 @@ where
     }
 
     fn write(&self, characteristic: &Characteristic, data: &[u8]) -> Result<()> {
-        self.p.write(characteristic, data, WriteType::WithoutResponse)
+        // Have the peripheral acknowledge the write whenever the characteristic supports it
+        let write_type = if characteristic.properties.contains(CharPropFlags::WRITE) {
+            WriteType::WithResponse
+        } else {
+            WriteType::WithoutResponse
+        };
+        self.p.write(characteristic, data, write_type)
     }
 }
 
 #[derive(Debug)]
 pub struct ConnectedBluetoothPeripheral {
     p: Box<dyn PeripheralWrapper>,
+    retry_policy: RetryPolicy,
+    // Characteristics by UUID, discovered the first time we need them
+    characteristics: Mutex<Option<HashMap<String, Characteristic>>>,
 }
 
 impl ConnectedBluetoothPeripheral {
-    pub fn new<P>(peripheral: P) -> Self
+    pub fn new<P>(peripheral: P, retry_policy: RetryPolicy) -> Self
     where
         P: Peripheral + 'static,
     {
-        let p = Box::new(BtleplugPeripheralWrapper::new(peripheral));
-        Self { p }
+        Self::with_wrapper(Box::new(BtleplugPeripheralWrapper::new(peripheral)), retry_policy)
+    }
+
+    fn with_wrapper(p: Box<dyn PeripheralWrapper>, retry_policy: RetryPolicy) -> Self {
+        Self {
+            p,
+            retry_policy,
+            characteristics: Mutex::new(None),
+        }
+    }
+
+    pub fn disconnect(&self) -> Result<()> {
+        self.p.disconnect()
+    }
+
+    /// Connects to the peripheral again after the connection has been lost.
+    /// The characteristics are discovered again, as the handles may have
+    /// changed in the meantime.
+    pub fn reconnect(&self) -> Result<()> {
+        self.invalidate_characteristics();
+        self.p.connect()?;
+        self.write_pin_code()
     }
 
-    pub fn disconnect(&self) {
-        self.p.disconnect().unwrap();
+    /// Forgets the discovered characteristics, so they will be discovered again
+    /// on next use.
+    pub fn invalidate_characteristics(&self) {
+        *self.characteristics.lock().unwrap() = None;
     }
 
     pub fn read_characteristics(&self, relevant_uuids: HashSet<String>) -> Result<HashMap<String, Vec<u8>>> {
-        let characteristics = self.p.discover_characteristics()?;
+        let uuids: Vec<String> = self.with_retries(|| self.with_discovered_characteristics(|characteristics| {
+            characteristics.keys().filter(|uuid| relevant_uuids.contains(*uuid)).cloned().collect()
+        }))?;
         let mut result = HashMap::new();
-        for characteristic in characteristics.iter() {
-            let uuid = characteristic.uuid.to_string();
-            if relevant_uuids.contains(&uuid) {
-                let data = self.read_data(&uuid).unwrap();
-                result.insert(uuid, data);
-            }
+        for uuid in uuids {
+            let data = self.read_data(&uuid)?;
+            result.insert(uuid, data);
         }
         Ok(result)
     }
     
-    pub fn send_pin_code(&self) {
-        let pin_code_data: Vec<u8> = vec![0 as u8, 0 as u8, 0 as u8, 0 as u8];
-        self.write_data(PIN_CODE_CHARACTERISTIC, &pin_code_data).unwrap();
+    /// All characteristics of the peripheral, in handle order where the
+    /// platform gives us handles.
+    pub fn characteristics(&self) -> Result<Vec<Characteristic>> {
+        let mut characteristics: Vec<Characteristic> = self.with_retries(|| self.with_discovered_characteristics(|characteristics| {
+            characteristics.values().cloned().collect()
+        }))?;
+        characteristics.sort_by_key(|characteristic| (characteristic.value_handle, characteristic.uuid.to_string()));
+        Ok(characteristics)
+    }
+
+    pub fn send_pin_code(&self) -> Result<()> {
+        self.with_retries(|| self.write_pin_code())?;
         eprintln!("Wrote pin code");
+        Ok(())
     }
     
     pub fn write_data(&self, characteristic: &str, data: &Vec<u8>) -> Result<()> {
-        let characteristics = self.p.discover_characteristics()?;
-        let bluetooth_characteristic = self.characteristic_with_uuid(&characteristics, characteristic);
-        self.p.write(bluetooth_characteristic, data)?;
-        Ok(())
+        self.with_retries(|| {
+            let bluetooth_characteristic = self.characteristic_with_uuid(characteristic)?;
+            self.p.write(&bluetooth_characteristic, data)
+        })
     }
     
     pub fn read_data(&self, characteristic: &str) -> Result<Vec<u8>> {
-        let characteristics = self.p.discover_characteristics()?;
-        let bluetooth_characteristic = self.characteristic_with_uuid(&characteristics, characteristic);
-        self.p.read(bluetooth_characteristic)
+        self.with_retries(|| {
+            let bluetooth_characteristic = self.characteristic_with_uuid(characteristic)?;
+            self.p.read(&bluetooth_characteristic)
+        })
+    }
+
+    /// Waits for the user to press the timer button on the thermostat, by
+    /// reading the secret key until the thermostat releases a valid one. This
+    /// lets front-ends pair without asking the user to confirm the button press.
+    ///
+    /// `on_progress` is called before each attempt and when the key has been
+    /// released.
+    pub fn wait_for_secret_key<F>(&self, timeout: Duration, poll_interval: Duration, mut on_progress: F) -> Result<Vec<u8>>
+    where
+        F: FnMut(PairingProgress),
+    {
+        let deadline = Instant::now() + timeout;
+        let mut attempt = 1;
+        loop {
+            let time_left = deadline.saturating_duration_since(Instant::now());
+            on_progress(PairingProgress::WaitingForTimerButton { attempt, time_left });
+
+            match self.try_read_secret_key() {
+                Ok(secret_key) if is_valid_secret_key(&secret_key) => {
+                    on_progress(PairingProgress::SecretKeyReleased);
+                    return Ok(secret_key);
+                }
+                Ok(_) => debug!("Secret key not released yet"),
+                Err(error) => debug!("Could not read secret key: {}", error),
+            }
+
+            if Instant::now() + poll_interval > deadline {
+                return Err(Error::TimedOut(timeout));
+            }
+            thread::sleep(poll_interval);
+            attempt += 1;
+        }
+    }
+
+    fn try_read_secret_key(&self) -> Result<Vec<u8>> {
+        if self.retry_policy.reconnect && !self.p.is_connected() {
+            self.reconnect()?;
+        }
+        let secret_key_characteristic = self.characteristic_with_uuid(SECRET_KEY)?;
+        self.p.read(&secret_key_characteristic)
+    }
+
+    fn write_pin_code(&self) -> Result<()> {
+        let pin_code_data: Vec<u8> = vec![0 as u8, 0 as u8, 0 as u8, 0 as u8];
+        let pin_code_characteristic = self.characteristic_with_uuid(PIN_CODE_CHARACTERISTIC)?;
+        self.p.write(&pin_code_characteristic, &pin_code_data)
+    }
+
+    fn with_retries<T, F>(&self, operation: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        self.retry_policy.run(operation, || {
+            if self.retry_policy.reconnect && !self.p.is_connected() {
+                eprintln!("Lost connection to peripheral. Reconnecting...");
+                // If this fails, the next attempt will fail as well and be retried
+                if let Err(error) = self.reconnect() {
+                    warn!("Could not reconnect: {}", error);
+                }
+            }
+        })
+    }
+
+    /// Runs `f` on the discovered characteristics while holding the lock, so
+    /// callers only copy what they need.
+    fn with_discovered_characteristics<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&HashMap<String, Characteristic>) -> T,
+    {
+        let mut characteristics = self.characteristics.lock().unwrap();
+        if characteristics.is_none() {
+            let discovered: HashMap<String, Characteristic> = self.p.discover_characteristics()?
+                .into_iter()
+                .map(|characteristic| (characteristic.uuid.to_string(), characteristic))
+                .collect();
+            *characteristics = Some(discovered);
+        }
+        Ok(f(characteristics.as_ref().unwrap()))
     }
     
-    fn characteristic_with_uuid<'a>(&self, characteristics: &'a Vec<Characteristic>, uuid: &str) -> &'a Characteristic {
-        characteristics
-            .iter()
-            .find(|&characteristic| characteristic.uuid.to_string() == uuid.to_string())
-            .unwrap()
-    }
-}
-
-pub fn scan(duration: Duration) -> Vec<ScannedBluetoothPeripheral> {
-    let manager = Manager::new().unwrap();
-    let central = get_central(&manager);
-    central.start_scan().unwrap();
-
-    // Panics after a while, at least on MacOS
-    /*match central.event_receiver() {
-      Some(receiver) => {
-        for i in 0..10 {
-          let event = receiver.recv().unwrap();
-          match event {
-            CentralEvent::DeviceDiscovered(address) => {
-              match central.peripheral(address) {
-                Some(peripheral) => {
-                  let local_name = peripheral.properties().local_name;
-                  match local_name {
-                    Some(name) => println!("Got a peripheral: {}", name),
-                    None => println!("Unknown peripheral")
-                  }
+    fn characteristic_with_uuid(&self, uuid: &str) -> Result<Characteristic> {
+        match self.with_discovered_characteristics(|characteristics| characteristics.get(uuid).cloned())? {
+            Some(characteristic) => Ok(characteristic),
+            None => Err(Error::NotSupported(format!("Peripheral has no characteristic {}", uuid))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PairingProgress {
+    /// We are connected, and waiting for the timer button to be pressed
+    WaitingForTimerButton { attempt: u32, time_left: Duration },
+    /// The thermostat has released its secret key
+    SecretKeyReleased,
+}
+
+// Until the timer button is pressed, the thermostat either refuses to give us
+// the secret key, or hands out zeros.
+fn is_valid_secret_key(secret_key: &[u8]) -> bool {
+    secret_key.len() == 16 && secret_key.iter().any(|byte| *byte != 0)
+}
+
+/// Hands the events of `events` on to the returned receiver from a thread that
+/// keeps draining `events` after the returned receiver is dropped.
+///
+/// btleplug only gives out the central's event receiver once, and panics in
+/// its own event thread if that receiver has been dropped, which poisons the
+/// peripheral state as soon as we connect.
+fn forward_events<T>(events: Receiver<T>) -> Receiver<T>
+where
+    T: Send + 'static,
+{
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        for event in events {
+            // Nobody listens once the session is gone, but we still have to drain
+            let _ = sender.send(event);
+        }
+    });
+    receiver
+}
+
+/// Iterates over named peripherals as the central discovers them, until the
+/// deadline has passed. Each peripheral is only yielded once.
+///
+/// We rely on the central events of the session when they are available, and
+/// fall back to polling the list of known peripherals otherwise.
+struct DiscoveredPeripherals<'a, P, C>
+where
+    P: Peripheral,
+    C: Central<P>,
+{
+    central: &'a C,
+    receiver: Option<&'a Receiver<CentralEvent>>,
+    deadline: Instant,
+    seen: HashSet<BDAddr>,
+    pending: VecDeque<P>,
+}
+
+impl<'a, P, C> DiscoveredPeripherals<'a, P, C>
+where
+    P: Peripheral,
+    C: Central<P>,
+{
+    fn start(central: &'a C, receiver: Option<&'a Receiver<CentralEvent>>, timeout: Duration) -> Result<Self> {
+        central.start_scan()?;
+
+        Ok(Self {
+            central,
+            receiver,
+            deadline: Instant::now() + timeout,
+            seen: HashSet::new(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    fn consider(&mut self, peripheral: P) {
+        // The name is sometimes not part of the first advertisement, so only
+        // remember peripherals once we know their name.
+        if peripheral.properties().local_name.is_some() && self.seen.insert(peripheral.address()) {
+            self.pending.push_back(peripheral);
+        }
+    }
+}
+
+impl<'a, P, C> Iterator for DiscoveredPeripherals<'a, P, C>
+where
+    P: Peripheral,
+    C: Central<P>,
+{
+    type Item = P;
+
+    fn next(&mut self) -> Option<P> {
+        loop {
+            if let Some(peripheral) = self.pending.pop_front() {
+                return Some(peripheral);
+            }
+
+            let now = Instant::now();
+            if now >= self.deadline {
+                return None;
+            }
+            let remaining = self.deadline - now;
+
+            match self.receiver {
+                Some(receiver) => match receiver.recv_timeout(remaining) {
+                    Ok(CentralEvent::DeviceDiscovered(address)) | Ok(CentralEvent::DeviceUpdated(address)) => {
+                        if let Some(peripheral) = self.central.peripheral(address) {
+                            self.consider(peripheral);
+                        }
+                    }
+                    Ok(_) => {} // Ignore
+                    Err(RecvTimeoutError::Timeout) => return None,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        warn!("Lost the central event receiver, falling back to polling");
+                        self.receiver = None;
+                    }
                 },
                 None => {
-                  println!("Warning: Discovered device unknown to central: {}", address)
+                    thread::sleep(remaining.min(POLL_INTERVAL));
+                    for peripheral in self.central.peripherals() {
+                        self.consider(peripheral);
+                    }
                 }
-              }
-            },
-            _ => {} // Ignore
-          }
+            }
         }
-      },
-      None => println!("Could not get an event receiver")
-    }*/
+    }
+}
+
+/// A connection to the Bluetooth adapter which can be used for several scans
+/// and connections, so we don't need to set up the adapter and scan from
+/// scratch for each thermostat.
+pub struct BluetoothSession {
+    // The central is only valid while the manager is alive
+    _manager: Manager,
+    central: Adapter,
+    // Taken once, before the first scan, so we don't miss the first events
+    events: Option<Receiver<CentralEvent>>,
+    retry_policy: RetryPolicy,
+}
+
+impl BluetoothSession {
+    /// Uses the given adapter, or the first one if none is given.
+    pub fn new(adapter: Option<&AdapterSelector>, retry_policy: RetryPolicy) -> Result<Self> {
+        let manager = Manager::new()?;
+        let central = get_central(&manager, adapter)?;
+        let events = central.event_receiver().map(forward_events);
+        Ok(Self {
+            _manager: manager,
+            central,
+            events,
+            retry_policy,
+        })
+    }
+
+    /// Scans for nearby peripherals for at most `timeout`, calling `on_discovered`
+    /// for each named peripheral as soon as it appears. Scanning stops early if
+    /// `on_discovered` returns `false`.
+    pub fn scan<F>(&self, timeout: Duration, mut on_discovered: F) -> Result<()>
+    where
+        F: FnMut(&ScannedBluetoothPeripheral) -> bool,
+    {
+        for peripheral in DiscoveredPeripherals::start(&self.central, self.events.as_ref(), timeout)? {
+            let properties = peripheral.properties();
+            let scanned_peripheral = ScannedBluetoothPeripheral {
+                name: properties.local_name.unwrap(),
+                address: properties.address.to_string(),
+                // btleplug 0.7 reports the RSSI of the latest advertisement in this field
+                rssi: properties.tx_power_level,
+            };
+            if !on_discovered(&scanned_peripheral) {
+                break;
+            }
+        }
+
+        self.central.stop_scan()
+    }
 
-    // Since the above commented-out code panics, let's just wait print out the
-    // peripherals discovered at the end.
-    thread::sleep(duration);
+    pub fn connect<F>(&self, matches_name: F, ensure_timer_button_pressed: bool) -> Result<ConnectedBluetoothPeripheral>
+        where F: Fn(&String) -> bool {
+        // If our peripheral does not appear within 2 minutes, we will give up.
+        let peripheral = DiscoveredPeripherals::start(&self.central, self.events.as_ref(), CONNECT_TIMEOUT)?
+            .find(|peripheral| matches_name(&peripheral.properties().local_name.unwrap()));
+        self.central.stop_scan()?;
 
-    central.stop_scan().unwrap();
+        let peripheral = match peripheral {
+            Some(p) => p,
+            None => return Err(Error::DeviceNotFound),
+        };
 
-    let mut result: Vec<ScannedBluetoothPeripheral> = vec![];
-    for peripheral in central.peripherals().iter() {
-        match peripheral.properties().local_name {
-            Some(name) => result.push(ScannedBluetoothPeripheral {
-                name: name,
-                address: peripheral.address().to_string(),
-            }),
-            None => {} // Ignore
+        eprintln!("Found thermostat");
+        if ensure_timer_button_pressed {
+            eprintln!("This is the first time you connect to this thermostat, so we need to fetch the secret key.");
+            eprintln!("Please click the timer button on the thermostat, then press enter on your keyboard to continue connecting.");
+            let mut input = String::new();
+            io::stdin().read_line(&mut input).unwrap();
         }
+
+        self.connect_to_peripheral(peripheral)
+    }
+
+    /// Connects to a peripheral found by an earlier scan in this session.
+    pub fn connect_to(&self, scanned_peripheral: &ScannedBluetoothPeripheral) -> Result<ConnectedBluetoothPeripheral> {
+        let address: BDAddr = scanned_peripheral.address.parse().map_err(|_| Error::DeviceNotFound)?;
+        match self.central.peripheral(address) {
+            Some(peripheral) => self.connect_to_peripheral(peripheral),
+            None => Err(Error::DeviceNotFound),
+        }
+    }
+
+    fn connect_to_peripheral<P>(&self, peripheral: P) -> Result<ConnectedBluetoothPeripheral>
+    where
+        P: Peripheral + 'static,
+    {
+        self.retry_policy.run(|| peripheral.connect(), || {})?;
+
+        let result = ConnectedBluetoothPeripheral::new(peripheral, self.retry_policy);
+        eprintln!("Connected to peripheral");
+        result.send_pin_code()?;
+
+        Ok(result)
     }
+}
 
-    result
+pub fn scan<F>(adapter: Option<&AdapterSelector>, timeout: Duration, on_discovered: F) -> Result<()>
+where
+    F: FnMut(&ScannedBluetoothPeripheral) -> bool,
+{
+    BluetoothSession::new(adapter, RetryPolicy::default())?.scan(timeout, on_discovered)
 }
 
-pub fn connect<F>(matches_name: F, ensure_timer_button_pressed: bool) -> Result<ConnectedBluetoothPeripheral>
+pub fn connect<F>(adapter: Option<&AdapterSelector>, matches_name: F, ensure_timer_button_pressed: bool, retry_policy: RetryPolicy) -> Result<ConnectedBluetoothPeripheral>
     where F: Fn(&String) -> bool {
-    let manager = Manager::new().unwrap();
-    let central = get_central(&manager);
-
-    // This is hacky: Just start scanning, and query for discovered peripherals every couple of seconds.
-    // If our peripheral does not appear within 2 minutes, we will give up.
-    central.start_scan().unwrap();
-
-    let end = SystemTime::now() + Duration::from_secs(120);
-    while SystemTime::now() < end {
-        eprint!(".");
-
-        for peripheral in central.peripherals().into_iter() {
-            match peripheral.properties().local_name {
-                Some(peripheral_name) => {
-                    if matches_name(&peripheral_name) {
-                        eprintln!("Found thermostat");
-                        if ensure_timer_button_pressed {
-                            eprintln!("This is the first time you connect to this thermostat, so we need to fetch the secret key.");
-                            eprintln!("Please click the timer button on the thermostat, then press enter on your keyboard to continue connecting.");
-                            let mut input = String::new();
-                            io::stdin().read_line(&mut input).unwrap();
-                        }
+    BluetoothSession::new(adapter, retry_policy)?.connect(matches_name, ensure_timer_button_pressed)
+}
 
-                        peripheral.connect()?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use btleplug::api::{AdapterManager, NotificationHandler, PeripheralProperties};
+    use std::collections::BTreeSet;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use uuid::Uuid;
 
-                        let result = ConnectedBluetoothPeripheral::new(peripheral);
-                        eprintln!("Connected to peripheral");
-                        result.send_pin_code();
+    #[derive(Debug, Default)]
+    struct FakePeripheral {
+        discoveries: Arc<AtomicUsize>,
+        connects: Arc<AtomicUsize>,
+        // The number of reads that will fail, after which the connection is lost
+        failing_reads: AtomicUsize,
+        // The number of secret key reads before the timer button is "pressed"
+        locked_secret_key_reads: AtomicUsize,
+        connected: AtomicBool,
+    }
 
-                        return Ok(result)
-                    }
+    impl PeripheralWrapper for FakePeripheral {
+        fn is_connected(&self) -> bool {
+            self.connected.load(Ordering::SeqCst)
+        }
+
+        fn connect(&self) -> Result<()> {
+            self.connects.fetch_add(1, Ordering::SeqCst);
+            self.connected.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn disconnect(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
+            self.discoveries.fetch_add(1, Ordering::SeqCst);
+            Ok([PIN_CODE_CHARACTERISTIC, BATTERY_LEVEL, DEVICE_NAME, SECRET_KEY].iter().map(|uuid| Characteristic {
+                start_handle: 0,
+                end_handle: 0,
+                value_handle: 0,
+                uuid: uuid.parse().unwrap(),
+                properties: CharPropFlags::READ,
+            }).collect())
+        }
+
+        fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+            if self.failing_reads.load(Ordering::SeqCst) > 0 {
+                self.failing_reads.fetch_sub(1, Ordering::SeqCst);
+                self.connected.store(false, Ordering::SeqCst);
+                return Err(Error::NotConnected);
+            }
+            if characteristic.uuid.to_string() == SECRET_KEY {
+                if self.locked_secret_key_reads.load(Ordering::SeqCst) > 0 {
+                    self.locked_secret_key_reads.fetch_sub(1, Ordering::SeqCst);
+                    return Ok(vec![0u8; 16]);
                 }
-                None => {} // Ignore
+                return Ok((1..=16).collect());
+            }
+            Ok(characteristic.uuid.to_string().into_bytes())
+        }
+
+        fn write(&self, _characteristic: &Characteristic, _data: &[u8]) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    struct TestPeripheral {
+        peripheral: ConnectedBluetoothPeripheral,
+        discoveries: Arc<AtomicUsize>,
+        connects: Arc<AtomicUsize>,
+    }
+
+    fn create_peripheral(failing_reads: usize, retry_policy: RetryPolicy) -> TestPeripheral {
+        let discoveries = Arc::new(AtomicUsize::new(0));
+        let connects = Arc::new(AtomicUsize::new(0));
+        let fake_peripheral = FakePeripheral {
+            discoveries: discoveries.clone(),
+            connects: connects.clone(),
+            failing_reads: AtomicUsize::new(failing_reads),
+            locked_secret_key_reads: AtomicUsize::new(2),
+            connected: AtomicBool::new(true),
+        };
+        TestPeripheral {
+            peripheral: ConnectedBluetoothPeripheral::with_wrapper(Box::new(fake_peripheral), retry_policy),
+            discoveries,
+            connects,
+        }
+    }
+
+    fn retry_policy(attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            attempts,
+            initial_backoff: Duration::from_millis(0),
+            reconnect: true,
+        }
+    }
+
+    #[test]
+    fn it_discovers_characteristics_only_once() {
+        let TestPeripheral { peripheral, discoveries, .. } = create_peripheral(0, retry_policy(1));
+
+        peripheral.send_pin_code().unwrap();
+        let values = peripheral.read_characteristics([BATTERY_LEVEL.to_string(), DEVICE_NAME.to_string()].iter().cloned().collect()).unwrap();
+        peripheral.read_data(BATTERY_LEVEL).unwrap();
+        peripheral.write_data(DEVICE_NAME, &vec![1u8, 2]).unwrap();
+
+        assert_eq!(2, values.len());
+        assert_eq!(BATTERY_LEVEL.as_bytes(), &values[BATTERY_LEVEL][..]);
+        assert_eq!(1, discoveries.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn it_discovers_characteristics_again_after_reconnecting() {
+        let TestPeripheral { peripheral, discoveries, .. } = create_peripheral(0, retry_policy(1));
+
+        peripheral.read_data(BATTERY_LEVEL).unwrap();
+        peripheral.reconnect().unwrap();
+        peripheral.read_data(BATTERY_LEVEL).unwrap();
+
+        assert_eq!(2, discoveries.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn it_lists_all_characteristics() {
+        let TestPeripheral { peripheral, .. } = create_peripheral(0, retry_policy(1));
+
+        let uuids: Vec<String> = peripheral.characteristics().unwrap().iter().map(|c| c.uuid.to_string()).collect();
+
+        assert_eq!(vec![BATTERY_LEVEL, PIN_CODE_CHARACTERISTIC, DEVICE_NAME, SECRET_KEY], uuids);
+    }
+
+    #[test]
+    fn it_fails_on_unknown_characteristic() {
+        let TestPeripheral { peripheral, discoveries, .. } = create_peripheral(0, retry_policy(3));
+
+        assert!(peripheral.read_data(SETTINGS).is_err());
+        // There's no point in retrying
+        assert_eq!(1, discoveries.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn it_waits_for_secret_key_to_be_released() {
+        let TestPeripheral { peripheral, .. } = create_peripheral(0, retry_policy(1));
+        let mut progress = vec![];
+
+        let secret_key = peripheral.wait_for_secret_key(Duration::from_secs(1), Duration::from_millis(0), |p| progress.push(p)).unwrap();
+
+        assert_eq!((1..=16).collect::<Vec<u8>>(), secret_key);
+        assert_eq!(4, progress.len());
+        assert!(matches!(progress[2], PairingProgress::WaitingForTimerButton { attempt: 3, .. }));
+        assert_eq!(PairingProgress::SecretKeyReleased, progress[3]);
+    }
+
+    #[test]
+    fn it_gives_up_waiting_for_secret_key_after_timeout() {
+        let TestPeripheral { peripheral, .. } = create_peripheral(0, retry_policy(1));
+
+        let result = peripheral.wait_for_secret_key(Duration::from_millis(0), Duration::from_millis(10), |_| {});
+
+        assert!(matches!(result, Err(Error::TimedOut(_))));
+    }
+
+    #[test]
+    fn it_knows_valid_secret_keys() {
+        assert!(is_valid_secret_key(&[215u8, 91, 125, 126, 14, 118, 62, 143, 121, 48, 110, 175, 112, 218, 245, 65]));
+        assert!(!is_valid_secret_key(&[0u8; 16]));
+        assert!(!is_valid_secret_key(&[1u8, 2, 3]));
+    }
+
+    #[test]
+    fn it_reconnects_and_retries_when_connection_is_lost() {
+        let TestPeripheral { peripheral, connects, .. } = create_peripheral(2, retry_policy(3));
+
+        assert_eq!(BATTERY_LEVEL.as_bytes(), &peripheral.read_data(BATTERY_LEVEL).unwrap()[..]);
+        assert_eq!(2, connects.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn it_gives_up_after_the_configured_number_of_attempts() {
+        let TestPeripheral { peripheral, connects, .. } = create_peripheral(3, retry_policy(3));
+
+        assert!(matches!(peripheral.read_data(BATTERY_LEVEL), Err(Error::NotConnected)));
+        assert_eq!(2, connects.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn it_does_not_reconnect_unless_asked_to() {
+        let retry_policy = RetryPolicy { reconnect: false, ..retry_policy(3) };
+        let TestPeripheral { peripheral, connects, .. } = create_peripheral(1, retry_policy);
+
+        assert!(peripheral.read_data(BATTERY_LEVEL).is_ok());
+        assert_eq!(0, connects.load(Ordering::SeqCst));
+    }
+
+    // Only what scanning needs. Connecting goes through PeripheralWrapper.
+    #[derive(Clone, Debug)]
+    struct NamedPeripheral {
+        address: BDAddr,
+        name: String,
+    }
+
+    impl Peripheral for NamedPeripheral {
+        fn address(&self) -> BDAddr {
+            self.address
+        }
+
+        fn properties(&self) -> PeripheralProperties {
+            PeripheralProperties {
+                address: self.address,
+                local_name: Some(self.name.clone()),
+                ..PeripheralProperties::default()
             }
         }
 
-        thread::sleep(Duration::from_secs(2));
+        fn characteristics(&self) -> BTreeSet<Characteristic> {
+            BTreeSet::new()
+        }
+
+        fn is_connected(&self) -> bool {
+            false
+        }
+
+        fn connect(&self) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn disconnect(&self) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
+            unimplemented!()
+        }
+
+        fn write(&self, _characteristic: &Characteristic, _data: &[u8], _write_type: WriteType) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn read(&self, _characteristic: &Characteristic) -> Result<Vec<u8>> {
+            unimplemented!()
+        }
+
+        fn read_by_type(&self, _characteristic: &Characteristic, _uuid: Uuid) -> Result<Vec<u8>> {
+            unimplemented!()
+        }
+
+        fn subscribe(&self, _characteristic: &Characteristic) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn unsubscribe(&self, _characteristic: &Characteristic) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn on_notification(&self, _handler: NotificationHandler) {
+            unimplemented!()
+        }
+    }
+
+    // Emits events through btleplug's own AdapterManager, which panics if its
+    // event receiver has been dropped
+    #[derive(Clone)]
+    struct FakeCentral {
+        manager: AdapterManager<NamedPeripheral>,
+    }
+
+    impl FakeCentral {
+        fn new() -> Self {
+            Self { manager: AdapterManager::new() }
+        }
+
+        fn discover(&self, address: &str, name: &str) {
+            let address: BDAddr = address.parse().unwrap();
+            self.manager.add_peripheral(address, NamedPeripheral { address, name: name.to_string() });
+            self.manager.emit(CentralEvent::DeviceDiscovered(address));
+        }
+    }
+
+    impl Central<NamedPeripheral> for FakeCentral {
+        fn event_receiver(&self) -> Option<Receiver<CentralEvent>> {
+            self.manager.event_receiver()
+        }
+
+        fn start_scan(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn active(&self, _enabled: bool) {}
+
+        fn filter_duplicates(&self, _enabled: bool) {}
+
+        fn stop_scan(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn peripherals(&self) -> Vec<NamedPeripheral> {
+            self.manager.peripherals()
+        }
+
+        fn peripheral(&self, address: BDAddr) -> Option<NamedPeripheral> {
+            self.manager.peripheral(address)
+        }
+    }
+
+    fn scanned_names(central: &FakeCentral, events: &Receiver<CentralEvent>) -> Vec<String> {
+        DiscoveredPeripherals::start(central, Some(events), Duration::from_millis(100)).unwrap()
+            .map(|peripheral| peripheral.name)
+            .collect()
     }
 
-    central.stop_scan().unwrap();
+    #[test]
+    fn it_keeps_draining_events_after_the_receiver_is_dropped() {
+        let (sender, events) = channel();
+        let receiver = forward_events(events);
+        sender.send(1).unwrap();
+        assert_eq!(Ok(1), receiver.recv());
 
-    Err(Error::NotConnected)
+        drop(receiver);
+        assert!(sender.send(2).is_ok());
+    }
+
+    #[test]
+    fn it_keeps_receiving_central_events_between_scans_and_connects() {
+        let central = FakeCentral::new();
+        let events = forward_events(central.event_receiver().unwrap());
+
+        central.discover("00:04:2F:06:24:D1", "0;0:04:2F:06:24:D1;eTRV");
+        assert_eq!(vec!["0;0:04:2F:06:24:D1;eTRV".to_string()], scanned_names(&central, &events));
+
+        // Connecting makes the central emit events while nobody is scanning
+        central.manager.emit(CentralEvent::DeviceConnected("00:04:2F:06:24:D1".parse().unwrap()));
+        central.manager.emit(CentralEvent::DeviceDisconnected("00:04:2F:06:24:D1".parse().unwrap()));
+
+        central.discover("00:04:2F:06:24:D2", "0;0:04:2F:06:24:D2;eTRV");
+        assert_eq!(vec!["0;0:04:2F:06:24:D2;eTRV".to_string()], scanned_names(&central, &events));
+    }
+
+    fn adapter_info() -> AdapterInfo {
+        AdapterInfo {
+            index: 1,
+            id: Some("hci1".to_string()),
+            name: Some("USB dongle".to_string()),
+            address: Some("00:1A:7D:DA:71:13".parse().unwrap()),
+            powered: Some(true),
+        }
+    }
+
+    #[test]
+    fn it_can_parse_adapter_selectors() {
+        assert_eq!(AdapterSelector::Index(1), AdapterSelector::parse("1"));
+        assert_eq!(AdapterSelector::Address("00:1A:7D:DA:71:13".parse().unwrap()), AdapterSelector::parse("00:1A:7D:DA:71:13"));
+        assert_eq!(AdapterSelector::Name("hci1".to_string()), AdapterSelector::parse("hci1"));
+    }
+
+    #[test]
+    fn it_matches_adapter_by_index_address_id_or_name() {
+        let adapter = adapter_info();
+
+        assert!(AdapterSelector::parse("1").matches(&adapter));
+        assert!(AdapterSelector::parse("00:1A:7D:DA:71:13").matches(&adapter));
+        assert!(AdapterSelector::parse("hci1").matches(&adapter));
+        assert!(AdapterSelector::parse("USB dongle").matches(&adapter));
+    }
+
+    #[test]
+    fn it_does_not_match_other_adapters() {
+        let adapter = adapter_info();
+
+        assert!(!AdapterSelector::parse("0").matches(&adapter));
+        assert!(!AdapterSelector::parse("00:1A:7D:DA:71:14").matches(&adapter));
+        assert!(!AdapterSelector::parse("hci0").matches(&adapter));
+    }
 }
@@ -0,0 +1,63 @@
+use crate::bluetooth::{self, AdapterInfo, AdapterSelector};
+use crate::commands::connection_options::adapter_or_default;
+
+pub fn execute(arguments: Vec<String>) {
+    if !arguments.is_empty() {
+        panic!("Expected no parameters to adapters. Got {}.", arguments.len());
+    }
+
+    let adapters = bluetooth::adapters().expect("Could not list Bluetooth adapters");
+    if adapters.is_empty() {
+        eprintln!("No Bluetooth adapters found");
+        std::process::exit(1);
+    }
+
+    // The adapter used when --adapter isn't given
+    let default_adapter = adapter_or_default(None).unwrap_or(AdapterSelector::Index(0));
+
+    println!("  {:<5} {:<6} {:<18} {:<7} NAME", "INDEX", "ID", "ADDRESS", "POWERED");
+    for adapter in adapters {
+        let marker = if default_adapter.matches(&adapter) { "*" } else { " " };
+        println!("{} {}", marker, table_row(&adapter));
+    }
+}
+
+fn table_row(adapter: &AdapterInfo) -> String {
+    let address = adapter.address.map(|address| address.to_string()).unwrap_or_else(|| "-".to_string());
+    let powered = match adapter.powered {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "-",
+    };
+    format!("{:<5} {:<6} {:<18} {:<7} {}",
+        adapter.index,
+        adapter.id.as_deref().unwrap_or("-"),
+        address,
+        powered,
+        adapter.name.as_deref().unwrap_or("-"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_shows_what_is_known_about_adapter() {
+        let adapter = AdapterInfo {
+            index: 1,
+            id: Some("hci1".to_string()),
+            name: Some("USB dongle".to_string()),
+            address: Some("00:1A:7D:DA:71:13".parse().unwrap()),
+            powered: Some(false),
+        };
+
+        assert_eq!("1     hci1   00:1A:7D:DA:71:13  no      USB dongle", table_row(&adapter));
+    }
+
+    #[test]
+    fn it_shows_dashes_for_unknown_details() {
+        let adapter = AdapterInfo { index: 0, ..Default::default() };
+
+        assert_eq!("0     -      -                  -       -", table_row(&adapter));
+    }
+}
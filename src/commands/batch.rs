@@ -0,0 +1,138 @@
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+use crate::bluetooth::{AdapterSelector, BluetoothSession, ConnectedBluetoothPeripheral, RetryPolicy, ScannedBluetoothPeripheral};
+use crate::models::thermostat_names::*;
+use crate::models::thermostats::{Thermostat, Thermostats};
+
+const SCAN_TIMEOUT: Duration = Duration::from_secs(120);
+const MAX_ATTEMPTS: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Succeeded { attempts: u32 },
+    Failed { attempts: u32, error: String },
+    NotFound,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Succeeded { attempts: 1 } => write!(f, "OK"),
+            Self::Succeeded { attempts } => write!(f, "OK after {} attempts", attempts),
+            Self::Failed { attempts, error } => write!(f, "Failed after {} attempts: {}", attempts, error),
+            Self::NotFound => write!(f, "Not found"),
+        }
+    }
+}
+
+/// Runs `operation` on all known thermostats within a single Bluetooth session.
+///
+/// We scan once, connect to the thermostats in the order they were discovered,
+/// and retry the failed ones with an increasing delay between each round. Each
+/// thermostat returned by `operation` is saved right away, so a later failure
+/// does not throw away what we have already read.
+///
+/// The retry policy is used for the individual Bluetooth operations within
+/// each round.
+pub fn execute_for_all<F>(adapter: Option<&AdapterSelector>, retry_policy: RetryPolicy, operation: F)
+where
+    F: Fn(&ConnectedBluetoothPeripheral, &Thermostat) -> btleplug::Result<Thermostat>,
+{
+    let mut thermostats = Thermostats::load().expect("Could not read thermostat data");
+    let known_serials: Vec<String> = thermostats.thermostats.iter().map(|t| t.serial.clone()).collect();
+    if known_serials.is_empty() {
+        eprintln!("No known thermostats. Use the read command on each thermostat first.");
+        std::process::exit(1);
+    }
+
+    let session = BluetoothSession::new(adapter, retry_policy).expect("Could not set up Bluetooth");
+
+    eprintln!("Scanning for {} known thermostats...", known_serials.len());
+    let mut discovered: Vec<(String, ScannedBluetoothPeripheral)> = vec![];
+    session.scan(SCAN_TIMEOUT, |peripheral| {
+        if is_thermostat_name(&peripheral.name) {
+            let serial = stripped_name(&peripheral.name);
+            if known_serials.contains(&serial) && !discovered.iter().any(|(s, _)| s == &serial) {
+                eprintln!("Found {}", serial);
+                discovered.push((serial, peripheral.clone()));
+            }
+        }
+        discovered.len() < known_serials.len()
+    }).expect("Could not scan for thermostats");
+
+    let mut outcomes: Vec<(String, Outcome)> = known_serials.iter()
+        .filter(|serial| !discovered.iter().any(|(s, _)| &s == serial))
+        .map(|serial| (serial.clone(), Outcome::NotFound))
+        .collect();
+
+    let mut remaining = discovered;
+    let mut backoff = INITIAL_BACKOFF;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let mut failed = vec![];
+        for (serial, peripheral) in remaining {
+            eprintln!("Connecting to {}...", serial);
+            let thermostat = thermostats.get(&serial).unwrap().clone();
+            match run_operation(&session, &peripheral, &thermostat, &operation) {
+                Ok(updated_thermostat) => {
+                    thermostats.push(updated_thermostat);
+                    thermostats.save().unwrap();
+                    outcomes.push((serial, Outcome::Succeeded { attempts: attempt }));
+                }
+                Err(error) => {
+                    eprintln!("Failed: {}", error);
+                    if attempt == MAX_ATTEMPTS {
+                        outcomes.push((serial, Outcome::Failed { attempts: attempt, error: error.to_string() }));
+                    } else {
+                        failed.push((serial, peripheral));
+                    }
+                }
+            }
+        }
+
+        remaining = failed;
+        if remaining.is_empty() || attempt == MAX_ATTEMPTS {
+            break;
+        }
+        eprintln!("Retrying {} thermostats in {} seconds...", remaining.len(), backoff.as_secs());
+        thread::sleep(backoff);
+        backoff *= 2;
+    }
+
+    println!();
+    println!("Summary:");
+    for (serial, outcome) in outcomes.iter() {
+        println!("{}: {}", serial, outcome);
+    }
+
+    if outcomes.iter().any(|(_, outcome)| !matches!(outcome, Outcome::Succeeded { .. })) {
+        std::process::exit(1);
+    }
+}
+
+fn run_operation<F>(session: &BluetoothSession, peripheral: &ScannedBluetoothPeripheral, thermostat: &Thermostat, operation: &F) -> btleplug::Result<Thermostat>
+where
+    F: Fn(&ConnectedBluetoothPeripheral, &Thermostat) -> btleplug::Result<Thermostat>,
+{
+    let connected_peripheral = session.connect_to(peripheral)?;
+    let result = operation(&connected_peripheral, thermostat);
+    if let Err(error) = connected_peripheral.disconnect() {
+        warn!("Could not disconnect from {}: {}", thermostat.serial, error);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_can_describe_outcomes() {
+        assert_eq!("OK", Outcome::Succeeded { attempts: 1 }.to_string());
+        assert_eq!("OK after 2 attempts", Outcome::Succeeded { attempts: 2 }.to_string());
+        assert_eq!("Failed after 3 attempts: Timed out", Outcome::Failed { attempts: 3, error: "Timed out".to_string() }.to_string());
+        assert_eq!("Not found", Outcome::NotFound.to_string());
+    }
+}
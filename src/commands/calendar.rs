@@ -0,0 +1,188 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::BufReader;
+use chrono::prelude::*;
+
+use crate::commands::{time_zone_option, vacation_period};
+use crate::models::config::Config;
+use crate::models::parsed_thermostat::ParsedThermostat;
+use crate::models::thermostats::{Thermostat, Thermostats};
+use crate::models::time_zone::TimeZoneSetting;
+use crate::models::vacation_calendar::{find_vacations, CalendarVacation};
+
+const IMPORT: &str = "import";
+const MATCH_OPTION: &str = "--match";
+const DEFAULT_KEYWORD: &str = "vacation";
+
+pub fn execute(arguments: Vec<String>) {
+    let (time_zone, arguments) = time_zone_option::take(arguments);
+    let (keyword, arguments) = take_keyword(arguments);
+    if arguments.first().map(|argument| argument.as_str()) != Some(IMPORT) {
+        panic!("Expected a calendar command: import");
+    }
+    if arguments.len() < 3 {
+        panic!("Expected a calendar file, and the serials of the thermostats to import vacations for, or --all");
+    }
+    let file_path = &arguments[1];
+    let targets = &arguments[2..];
+
+    let mut thermostats = Thermostats::load().expect("Could not read thermostat data");
+    let serials: Vec<String> = if targets == ["--all"] {
+        thermostats.thermostats.iter().map(|thermostat| thermostat.serial.clone()).collect()
+    } else {
+        targets.to_vec()
+    };
+    for serial in &serials {
+        if thermostats.get(serial).is_none() {
+            panic!("Thermostat with serial {} not found. Have you run the read command first?", serial);
+        }
+    }
+
+    let file = File::open(file_path).unwrap_or_else(|error| panic!("Could not open {}: {}", file_path, error));
+    let events = match find_vacations(BufReader::new(file), &keyword, time_zone) {
+        Ok(events) => events,
+        Err(invalid_calendar) => {
+            eprintln!("{}", invalid_calendar);
+            std::process::exit(1);
+        }
+    };
+    let now = Utc::now();
+    let mut vacations = vec![];
+    for event in events {
+        match event {
+            Ok(vacation) => vacations.extend(whole_hours(vacation, time_zone).filter(|vacation| vacation.end > now)),
+            Err(invalid_event) => eprintln!("{}", invalid_event),
+        }
+    }
+    if vacations.is_empty() {
+        println!("No upcoming events matching '{}' in {}", keyword, file_path);
+        return;
+    }
+
+    for serial in &serials {
+        let mut thermostat = thermostats.get(serial).unwrap().clone();
+        queue_vacations(&mut thermostat, &vacations, time_zone);
+        thermostats.push(thermostat);
+    }
+    thermostats.save().unwrap();
+}
+
+// Takes `--match` out of the arguments. Without it, the keyword comes from the
+// config, and is "vacation" if it doesn't have one.
+fn take_keyword(arguments: Vec<String>) -> (String, Vec<String>) {
+    let mut keyword = None;
+    let mut remaining_arguments = vec![];
+
+    let mut arguments = arguments.into_iter();
+    while let Some(argument) = arguments.next() {
+        if argument == MATCH_OPTION {
+            keyword = Some(arguments.next().expect("Expected a category or word after --match"));
+        } else {
+            remaining_arguments.push(argument);
+        }
+    }
+
+    let keyword = keyword.unwrap_or_else(|| {
+        let config = Config::load().expect("Could not read config");
+        config.calendar_keyword.unwrap_or_else(|| DEFAULT_KEYWORD.to_string())
+    });
+    (keyword, remaining_arguments)
+}
+
+// The thermostat only supports whole hours, in the time zone they are shown in
+fn whole_hours(vacation: CalendarVacation, time_zone: TimeZoneSetting) -> Option<CalendarVacation> {
+    let (start, end) = match time_zone {
+        TimeZoneSetting::Local => (round_in(vacation.start, &Local), round_in(vacation.end, &Local)),
+        TimeZoneSetting::Named(tz) => (round_in(vacation.start, &tz), round_in(vacation.end, &tz)),
+    };
+    if end <= start {
+        eprintln!("Skipping '{}': it is shorter than an hour", vacation.summary);
+        return None;
+    }
+    if start != vacation.start || end != vacation.end {
+        eprintln!("The thermostat only supports whole hours. Rounding '{}' to {} - {}", vacation.summary, time_zone.format(start), time_zone.format(end));
+    }
+    Some(CalendarVacation { start, end, ..vacation })
+}
+
+fn round_in<Tz: TimeZone>(time: DateTime<Utc>, tz: &Tz) -> DateTime<Utc> {
+    vacation_period::round_to_whole_hour(&time.with_timezone(tz)).with_timezone(&Utc)
+}
+
+// Importing the same calendar again leaves the vacations that are already
+// queued, or written to the thermostat, alone
+fn queue_vacations(thermostat: &mut Thermostat, vacations: &[CalendarVacation], time_zone: TimeZoneSetting) {
+    let programmed_vacation_period = ParsedThermostat::try_from(&*thermostat).ok()
+        .and_then(|parsed_thermostat| parsed_thermostat.vacation_period)
+        .map(|(start, end)| (start.timestamp(), end.timestamp()));
+
+    for vacation in vacations {
+        let period = (vacation.start.timestamp(), vacation.end.timestamp());
+        if Some(period) == programmed_vacation_period || Some(period) == thermostat.new_vacation_period {
+            continue;
+        }
+        match thermostat.queue_vacation_period(period.0, period.1) {
+            Ok(()) => println!("{}: Queued vacation: {} - {} ({})", thermostat.serial, time_zone.format(vacation.start), time_zone.format(vacation.end), vacation.summary),
+            Err(overlapping_period) if overlapping_period == period => {}
+            Err((overlapping_start, overlapping_end)) => eprintln!(
+                "{}: Skipping '{}', as it overlaps the queued vacation period {} - {}",
+                thermostat.serial,
+                vacation.summary,
+                time_zone.format(Utc.timestamp(overlapping_start, 0)),
+                time_zone.format(Utc.timestamp(overlapping_end, 0))
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vacation(summary: &str, start: DateTime<Utc>, end: DateTime<Utc>) -> CalendarVacation {
+        CalendarVacation { summary: summary.to_string(), start, end }
+    }
+
+    #[test]
+    fn it_rounds_vacations_to_whole_hours() {
+        let time_zone = TimeZoneSetting::Named(chrono_tz::Europe::Copenhagen);
+        let rounded = whole_hours(vacation("Trip", Utc.ymd(2026, 12, 21).and_hms(6, 20, 0), Utc.ymd(2026, 12, 28).and_hms(17, 45, 0)), time_zone);
+
+        assert_eq!(Some(vacation("Trip", Utc.ymd(2026, 12, 21).and_hms(6, 0, 0), Utc.ymd(2026, 12, 28).and_hms(18, 0, 0))), rounded);
+    }
+
+    #[test]
+    fn it_rounds_in_the_time_zone_the_vacations_are_shown_in() {
+        let time_zone = TimeZoneSetting::Named(chrono_tz::Asia::Kolkata);
+        let rounded = whole_hours(vacation("Trip", Utc.ymd(2026, 12, 21).and_hms(6, 20, 0), Utc.ymd(2026, 12, 28).and_hms(6, 30, 0)), time_zone);
+
+        assert_eq!(Some(vacation("Trip", Utc.ymd(2026, 12, 21).and_hms(6, 30, 0), Utc.ymd(2026, 12, 28).and_hms(6, 30, 0))), rounded);
+    }
+
+    #[test]
+    fn it_skips_vacations_shorter_than_an_hour() {
+        let time_zone = TimeZoneSetting::Named(chrono_tz::Europe::Copenhagen);
+
+        assert_eq!(None, whole_hours(vacation("Lunch", Utc.ymd(2026, 12, 21).and_hms(11, 10, 0), Utc.ymd(2026, 12, 21).and_hms(11, 25, 0)), time_zone));
+    }
+
+    #[test]
+    fn it_does_not_queue_vacations_twice() {
+        let time_zone = TimeZoneSetting::Named(chrono_tz::Europe::Copenhagen);
+        let mut thermostat = Thermostat {
+            new_vacation_period: Some((Utc.ymd(2026, 11, 1).and_hms(23, 0, 0).timestamp(), Utc.ymd(2026, 11, 8).and_hms(23, 0, 0).timestamp())),
+            ..Default::default()
+        };
+        let vacations = vec![
+            vacation("Autumn", Utc.ymd(2026, 11, 1).and_hms(23, 0, 0), Utc.ymd(2026, 11, 8).and_hms(23, 0, 0)),
+            vacation("Christmas", Utc.ymd(2026, 12, 20).and_hms(23, 0, 0), Utc.ymd(2026, 12, 27).and_hms(23, 0, 0)),
+            vacation("Christmas dinner", Utc.ymd(2026, 12, 24).and_hms(17, 0, 0), Utc.ymd(2026, 12, 24).and_hms(23, 0, 0)),
+        ];
+
+        queue_vacations(&mut thermostat, &vacations, time_zone);
+        queue_vacations(&mut thermostat, &vacations, time_zone);
+
+        let expected_periods = vec![(Utc.ymd(2026, 12, 20).and_hms(23, 0, 0).timestamp(), Utc.ymd(2026, 12, 27).and_hms(23, 0, 0).timestamp())];
+        assert_eq!(expected_periods, thermostat.queued_vacation_periods);
+    }
+}
@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+use crate::bluetooth::{AdapterSelector, RetryPolicy};
+use crate::models::config::Config;
+
+// The backoff doubles for each retry, so keep it well away from overflowing
+const MAX_BACKOFF_SECONDS: f32 = 3600.0;
+
+#[derive(Debug, PartialEq)]
+pub enum Target {
+    Serial(String),
+    All,
+}
+
+/// Options shared by the commands that connect to thermostats.
+#[derive(Debug, PartialEq)]
+pub struct ConnectionOptions {
+    pub target: Target,
+    pub adapter: Option<AdapterSelector>,
+    pub retry_policy: RetryPolicy,
+}
+
+pub fn parse(command: &str, arguments: &[String]) -> ConnectionOptions {
+    parse_with_default_adapter(command, arguments, default_adapter)
+}
+
+// The default adapter is only looked up when --adapter isn't given
+fn parse_with_default_adapter(command: &str, arguments: &[String], default_adapter: fn() -> Option<AdapterSelector>) -> ConnectionOptions {
+    let mut target = None;
+    let mut adapter = None;
+    let mut retry_policy = RetryPolicy::default();
+
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--all" => target = Some(Target::All),
+            "--adapter" => adapter = Some(parse_adapter(arguments.next())),
+            "--attempts" => {
+                let attempts = arguments.next().expect("Expected a number of attempts after --attempts");
+                retry_policy.attempts = attempts.parse().expect("Cannot parse supplied number of attempts");
+                if retry_policy.attempts == 0 {
+                    panic!("Expected at least one attempt");
+                }
+            }
+            "--backoff" => {
+                let seconds = arguments.next().expect("Expected a number of seconds after --backoff");
+                let seconds = seconds.parse::<f32>().expect("Cannot parse supplied backoff");
+                if !(0.0..=MAX_BACKOFF_SECONDS).contains(&seconds) {
+                    panic!("Expected the backoff to be between 0 and {} seconds", MAX_BACKOFF_SECONDS);
+                }
+                retry_policy.initial_backoff = Duration::from_secs_f32(seconds);
+            }
+            "--no-reconnect" => retry_policy.reconnect = false,
+            _ if argument.starts_with("--") => panic!("Unknown option to {}: {}", command, argument),
+            _ if target.is_some() => panic!("Expected just one thermostat serial to {}", command),
+            _ => target = Some(Target::Serial(argument.clone())),
+        }
+    }
+
+    match target {
+        Some(target) => ConnectionOptions { target, adapter: adapter.or_else(default_adapter), retry_policy },
+        None => panic!("Expected a thermostat serial or --all to {}", command),
+    }
+}
+
+pub fn parse_adapter(argument: Option<&String>) -> AdapterSelector {
+    let adapter = argument.expect("Expected an adapter name, index or address after --adapter");
+    AdapterSelector::parse(adapter)
+}
+
+/// Falls back to the adapter from the config if none was given on the command line.
+pub fn adapter_or_default(adapter: Option<AdapterSelector>) -> Option<AdapterSelector> {
+    adapter.or_else(default_adapter)
+}
+
+fn default_adapter() -> Option<AdapterSelector> {
+    let config = Config::load().expect("Could not read config");
+    config.adapter.map(|adapter| AdapterSelector::parse(&adapter))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::arguments;
+
+    fn parse_without_config(command: &str, arguments: &[String]) -> ConnectionOptions {
+        parse_with_default_adapter(command, arguments, || None)
+    }
+
+    #[test]
+    fn it_can_parse_serial_with_default_retry_policy() {
+        let options = parse_without_config("read", &arguments(&["0:04:2F:06:24:D1"]));
+
+        assert_eq!(Target::Serial("0:04:2F:06:24:D1".to_string()), options.target);
+        assert_eq!(None, options.adapter);
+        assert_eq!(RetryPolicy::default(), options.retry_policy);
+    }
+
+    #[test]
+    fn it_can_parse_adapter() {
+        let options = parse_without_config("read", &arguments(&["--adapter", "hci1", "0:04:2F:06:24:D1"]));
+
+        assert_eq!(Some(AdapterSelector::Name("hci1".to_string())), options.adapter);
+        assert_eq!(Target::Serial("0:04:2F:06:24:D1".to_string()), options.target);
+    }
+
+    #[test]
+    fn it_falls_back_to_the_default_adapter() {
+        let default_adapter = || Some(AdapterSelector::Index(1));
+
+        assert_eq!(Some(AdapterSelector::Index(1)), parse_with_default_adapter("read", &arguments(&["0:04:2F:06:24:D1"]), default_adapter).adapter);
+        assert_eq!(Some(AdapterSelector::Name("hci0".to_string())), parse_with_default_adapter("read", &arguments(&["--adapter", "hci0", "0:04:2F:06:24:D1"]), default_adapter).adapter);
+    }
+
+    #[test]
+    fn it_can_parse_all_and_retry_policy() {
+        let options = parse_without_config("sync", &arguments(&["--all", "--attempts", "5", "--backoff", "0.5", "--no-reconnect"]));
+
+        assert_eq!(Target::All, options.target);
+        assert_eq!(RetryPolicy {
+            attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            reconnect: false,
+        }, options.retry_policy);
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected a thermostat serial or --all to read")]
+    fn it_panics_without_target() {
+        parse_without_config("read", &arguments(&["--attempts", "2"]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown option to sync: --foo")]
+    fn it_panics_on_unknown_option() {
+        parse_without_config("sync", &arguments(&["0:04:2F:06:24:D1", "--foo"]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected at least one attempt")]
+    fn it_panics_on_zero_attempts() {
+        parse_without_config("sync", &arguments(&["0:04:2F:06:24:D1", "--attempts", "0"]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected the backoff to be between 0 and 3600 seconds")]
+    fn it_panics_on_negative_backoff() {
+        parse_without_config("sync", &arguments(&["0:04:2F:06:24:D1", "--backoff", "-1"]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected the backoff to be between 0 and 3600 seconds")]
+    fn it_panics_on_backoff_that_is_not_a_number() {
+        parse_without_config("sync", &arguments(&["0:04:2F:06:24:D1", "--backoff", "NaN"]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected the backoff to be between 0 and 3600 seconds")]
+    fn it_panics_on_infinite_backoff() {
+        parse_without_config("sync", &arguments(&["0:04:2F:06:24:D1", "--backoff", "inf"]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected the backoff to be between 0 and 3600 seconds")]
+    fn it_panics_on_too_long_backoff() {
+        parse_without_config("sync", &arguments(&["0:04:2F:06:24:D1", "--backoff", "1e30"]));
+    }
+}
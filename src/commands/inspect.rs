@@ -0,0 +1,170 @@
+use std::ops::Range;
+use std::path::Path;
+
+use crate::commands::raw::to_hex;
+use crate::models::characteristic_layout::{layouts, Row};
+use crate::models::thermostats::{Thermostat, Thermostats};
+
+#[derive(Debug, PartialEq)]
+struct InspectArguments {
+    serial: String,
+    diff: Option<String>,
+}
+
+pub fn execute(arguments: Vec<String>) {
+    let arguments = parse_arguments(&arguments);
+
+    let thermostats = Thermostats::load().expect("Could not read thermostat data");
+    let thermostat = find_thermostat(&thermostats, &arguments.serial, "Have you called the 'read' command first?");
+
+    let lines = match &arguments.diff {
+        None => inspection(thermostat),
+        Some(file_path) => {
+            if !Path::new(file_path).exists() {
+                panic!("Cannot find {}", file_path);
+            }
+            let earlier_thermostats = Thermostats::load_from(file_path).expect("Could not read earlier thermostat data");
+            let earlier_thermostat = find_thermostat(&earlier_thermostats, &arguments.serial, "Is it the right file?");
+            diff(earlier_thermostat, thermostat)
+        }
+    };
+    for line in lines {
+        println!("{}", line);
+    }
+}
+
+fn parse_arguments(arguments: &[String]) -> InspectArguments {
+    let mut serial = None;
+    let mut diff = None;
+
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--diff" => diff = Some(arguments.next().expect("Expected a thermostats file after --diff").clone()),
+            _ if argument.starts_with("--") => panic!("Unknown option to inspect: {}", argument),
+            _ if serial.is_some() => panic!("Expected just one thermostat serial to inspect"),
+            _ => serial = Some(argument.clone()),
+        }
+    }
+
+    InspectArguments {
+        serial: serial.expect("Expected a thermostat serial to inspect"),
+        diff,
+    }
+}
+
+fn find_thermostat<'a>(thermostats: &'a Thermostats, serial: &String, hint: &str) -> &'a Thermostat {
+    match thermostats.get(serial) {
+        Some(thermostat) => thermostat,
+        None => panic!("Don't know about any thermostats with name '{}'. {}", serial, hint),
+    }
+}
+
+/// Each stored characteristic, decrypted and split into known fields and unknown bytes
+fn inspection(thermostat: &Thermostat) -> Vec<String> {
+    let mut lines = vec![];
+    for layout in layouts() {
+        let value = layout.decrypted_value(thermostat);
+        lines.push(format!("{} ({} bytes)", layout.name, value.len()));
+        for row in layout.rows(&value) {
+            let (field_name, field_value) = describe(&row, &value[row.bytes.clone()]);
+            lines.push(format!("  {:<6} {:<18} {:<29} {}", offsets(&row.bytes), to_hex(&value[row.bytes.clone()]), field_name, field_value).trim_end().to_string());
+        }
+        lines.push("".to_string());
+    }
+    lines
+}
+
+/// The fields and unknown bytes that differ between two snapshots of a thermostat
+fn diff(before: &Thermostat, after: &Thermostat) -> Vec<String> {
+    let mut lines = vec![];
+    for layout in layouts() {
+        let before_value = layout.decrypted_value(before);
+        let after_value = layout.decrypted_value(after);
+        let longest_value = if after_value.len() >= before_value.len() { &after_value } else { &before_value };
+
+        let changed_rows: Vec<Row> = layout.rows(longest_value).into_iter()
+            .filter(|row| before_value.get(row.bytes.clone()) != after_value.get(row.bytes.clone()))
+            .collect();
+        if changed_rows.is_empty() {
+            continue;
+        }
+
+        lines.push(layout.name.to_string());
+        for row in changed_rows {
+            let before_bytes = before_value.get(row.bytes.clone()).unwrap_or(&[]);
+            let after_bytes = after_value.get(row.bytes.clone()).unwrap_or(&[]);
+            let (field_name, before_description) = describe(&row, before_bytes);
+            let (_, after_description) = describe(&row, after_bytes);
+            lines.push(format!("  {:<6} {} -> {}  {}", offsets(&row.bytes), to_hex(before_bytes), to_hex(after_bytes), field_name));
+            if !before_description.is_empty() {
+                lines.push(format!("         {} -> {}", before_description, after_description));
+            }
+        }
+    }
+
+    if lines.is_empty() {
+        lines.push("No differences".to_string());
+    }
+    lines
+}
+
+fn describe(row: &Row, bytes: &[u8]) -> (String, String) {
+    match &row.field {
+        Some(field) if bytes.len() == row.bytes.len() => (field.name.to_string(), field.decode(bytes)),
+        Some(field) => (field.name.to_string(), "-".to_string()),
+        None => ("unknown".to_string(), "".to_string()),
+    }
+}
+
+fn offsets(bytes: &Range<usize>) -> String {
+    if bytes.len() == 1 {
+        bytes.start.to_string()
+    } else {
+        format!("{}-{}", bytes.start, bytes.end - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{arguments, create_scheduled_thermostat};
+    use crate::models::parsed_thermostat::update_schedule_mode;
+
+    #[test]
+    fn it_can_parse_serial_and_diff() {
+        assert_eq!(InspectArguments {
+            serial: "0:04:2F:06:24:D1".to_string(),
+            diff: Some("before.json".to_string()),
+        }, parse_arguments(&arguments(&["0:04:2F:06:24:D1", "--diff", "before.json"])));
+    }
+
+    #[test]
+    fn it_annotates_known_fields_and_unknown_bytes() {
+        let lines = inspection(&create_scheduled_thermostat());
+
+        assert_eq!("name (16 bytes)", lines[0]);
+        assert_eq!("  0-15   41 6c 72 75 6d 20 6f 70 67 61 6e 67 00 00 00 00 name                          Alrum opgang", lines[1]);
+        assert!(lines.contains(&"battery level (1 bytes)".to_string()));
+        assert!(lines.iter().any(|line| line.starts_with("  4      01") && line.ends_with("schedule mode                 Scheduled")));
+        assert!(lines.iter().any(|line| line.starts_with("  14 ") && line.ends_with("unknown")));
+    }
+
+    #[test]
+    fn it_shows_only_changed_fields_in_diff() {
+        let before = create_scheduled_thermostat();
+        let mut after = create_scheduled_thermostat();
+        after.settings = update_schedule_mode(&before.settings, &before.secret, 3).unwrap();
+
+        assert_eq!(vec![
+            "settings".to_string(),
+            "  4      01 -> 03  schedule mode".to_string(),
+            "         Scheduled -> Vacation".to_string(),
+        ], diff(&before, &after));
+    }
+
+    #[test]
+    fn it_tells_when_there_are_no_differences() {
+        assert_eq!(vec!["No differences".to_string()], diff(&create_scheduled_thermostat(), &create_scheduled_thermostat()));
+    }
+}
@@ -1,7 +1,12 @@
+use std::convert::TryFrom;
+
+use crate::commands::unit_option;
 use crate::models::thermostats::Thermostats;
 use crate::models::parsed_thermostat::ParsedThermostat;
+use crate::models::secret_check::check_secret;
 
 pub fn execute(arguments: Vec<String>) {
+    let (unit, arguments) = unit_option::take(arguments);
     if arguments.len() != 0 {
         panic!("Expected no parameters to list. Got {}.", arguments.len());
     }
@@ -11,7 +16,17 @@ pub fn execute(arguments: Vec<String>) {
     thermostats.sort_by(|t1, t2| t1.name.cmp(&t2.name));
 
     for thermostat in thermostats {
-        let parsed_thermostat = ParsedThermostat::from_thermostat(&thermostat);
-        println!("{} {} {}%", thermostat.serial, parsed_thermostat.name, parsed_thermostat.battery_percentage);
+        if thermostat.needs_repairing || check_secret(&thermostat).is_err() {
+            println!("{} needs to be paired again (eco2 repair {})", thermostat.serial, thermostat.serial);
+            continue;
+        }
+        // Keep listing the other thermostats if one of them is broken
+        match ParsedThermostat::try_from(&thermostat) {
+            Ok(parsed_thermostat) => {
+                let child_lock = if parsed_thermostat.device_settings.child_lock { " child-locked" } else { "" };
+                println!("{} {} {}% {}{}", thermostat.serial, parsed_thermostat.name, parsed_thermostat.battery_percentage, unit.format(parsed_thermostat.room_temperature), child_lock);
+            }
+            Err(error) => eprintln!("{}: {}. Try reading it again.", thermostat.serial, error),
+        }
     }
 }
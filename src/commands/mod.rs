@@ -1,7 +1,17 @@
+pub mod adapters;
+pub mod batch;
+pub mod calendar;
+pub mod connection_options;
 pub mod forget;
+pub mod inspect;
 pub mod list;
+pub mod raw;
 pub mod read;
+pub mod repair;
 pub mod scan;
 pub mod set;
 pub mod show;
 pub mod sync;
+pub mod time_zone_option;
+pub mod unit_option;
+pub mod vacation_period;
@@ -0,0 +1,236 @@
+use btleplug::api::{CharPropFlags, Characteristic};
+
+use crate::bluetooth;
+use crate::bluetooth::ConnectedBluetoothPeripheral;
+use crate::commands::connection_options::{self, ConnectionOptions, Target};
+use crate::encryption::{can_decrypt, decrypt, encrypt};
+use crate::models::thermostat_names::*;
+use crate::models::thermostats::Thermostats;
+
+/// Tools for exploring the parts of the protocol we don't know yet.
+pub fn execute(arguments: Vec<String>) {
+    let mut arguments = arguments.into_iter();
+    match arguments.next().as_deref() {
+        Some("dump") => dump(arguments.collect()),
+        Some("write") => write(arguments.collect()),
+        Some(subcommand) => panic!("Unknown raw command {}. Expected dump or write.", subcommand),
+        None => panic!("Expected dump or write after raw"),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct RawWrite {
+    uuid: String,
+    data: Vec<u8>,
+    encrypt: bool,
+    confirmed: bool,
+}
+
+fn dump(arguments: Vec<String>) {
+    let options = connection_options::parse("raw dump", &arguments);
+    let serial = single_serial(&options);
+    let secret = Thermostats::load().unwrap().get(serial).map(|t| t.secret.clone());
+    if secret.is_none() {
+        eprintln!("No secret key known for {}, so values will not be decrypted.", serial);
+    }
+
+    let result = with_connected_peripheral(serial, &options, |peripheral| {
+        for characteristic in peripheral.characteristics()? {
+            println!("{}", describe_characteristic(&characteristic));
+            if !characteristic.properties.contains(CharPropFlags::READ) {
+                continue;
+            }
+            match peripheral.read_data(&characteristic.uuid.to_string()) {
+                Ok(value) => {
+                    println!("  hex:       {}", to_hex(&value));
+                    if let Some(secret) = &secret {
+                        if can_decrypt(&value) {
+                            println!("  decrypted: {}", to_hex(&decrypt(secret, &value)));
+                        }
+                    }
+                }
+                Err(error) => println!("  could not read: {}", error),
+            }
+        }
+        Ok(())
+    });
+    if let Err(error) = result {
+        eprintln!("Could not dump {}: {}", serial, error);
+        std::process::exit(1);
+    }
+}
+
+fn write(arguments: Vec<String>) {
+    let (raw_write, arguments) = parse_write(arguments);
+    let options = connection_options::parse("raw write", &arguments);
+    let serial = single_serial(&options);
+
+    let data = if raw_write.encrypt {
+        let thermostats = Thermostats::load().unwrap();
+        let secret = &thermostats.get(serial).expect("Unknown thermostat serial. You need to do a read first to encrypt.").secret;
+        if !can_decrypt(&raw_write.data) {
+            panic!("Only data of at least 8 bytes, in multiples of 4 bytes, can be encrypted");
+        }
+        encrypt(secret, &raw_write.data)
+    } else {
+        raw_write.data.clone()
+    };
+
+    eprintln!("Writing {} to {} on {}", to_hex(&data), raw_write.uuid, serial);
+    if !raw_write.confirmed {
+        eprintln!("Writing arbitrary data may leave the thermostat in a bad state. Add --confirm to write anyway.");
+        std::process::exit(1);
+    }
+
+    let result = with_connected_peripheral(serial, &options, |peripheral| {
+        peripheral.write_data(&raw_write.uuid, &data)
+    });
+    if let Err(error) = result {
+        eprintln!("Could not write to {}: {}", serial, error);
+        std::process::exit(1);
+    }
+}
+
+// Takes out the uuid, data and write options, leaving the connection options
+fn parse_write(arguments: Vec<String>) -> (RawWrite, Vec<String>) {
+    if arguments.len() < 3 {
+        panic!("Expected a thermostat serial, a characteristic UUID, and hex data to raw write");
+    }
+
+    let mut arguments = arguments.into_iter();
+    let mut remaining_arguments = vec![arguments.next().unwrap()];
+    let mut raw_write = RawWrite {
+        uuid: arguments.next().unwrap().to_lowercase(),
+        data: from_hex(&arguments.next().unwrap()),
+        encrypt: false,
+        confirmed: false,
+    };
+    for argument in arguments {
+        match argument.as_str() {
+            "--encrypt" => raw_write.encrypt = true,
+            "--confirm" => raw_write.confirmed = true,
+            _ => remaining_arguments.push(argument),
+        }
+    }
+
+    (raw_write, remaining_arguments)
+}
+
+fn single_serial(options: &ConnectionOptions) -> &String {
+    match &options.target {
+        Target::All => panic!("Raw commands work on one thermostat at a time. Expected a thermostat serial."),
+        Target::Serial(serial) => serial,
+    }
+}
+
+fn with_connected_peripheral<F>(serial: &String, options: &ConnectionOptions, operation: F) -> btleplug::Result<()>
+where
+    F: FnOnce(&ConnectedBluetoothPeripheral) -> btleplug::Result<()>,
+{
+    let connected_peripheral = bluetooth::connect(options.adapter.as_ref(), |name| is_thermostat_name(name) && &stripped_name(name) == serial, false, options.retry_policy)?;
+    let result = operation(&connected_peripheral);
+    if let Err(error) = connected_peripheral.disconnect() {
+        warn!("Could not disconnect from {}: {}", serial, error);
+    }
+    result
+}
+
+fn describe_characteristic(characteristic: &Characteristic) -> String {
+    let uuid = characteristic.uuid.to_string();
+    let mut description = format!("{} handle {:#06x} {:?}", uuid, characteristic.value_handle, characteristic.properties);
+    if let Some(name) = known_name(&uuid) {
+        description.push_str(&format!(" ({})", name));
+    }
+    description
+}
+
+fn known_name(uuid: &str) -> Option<&'static str> {
+    match uuid {
+        bluetooth::BATTERY_LEVEL => Some("battery level"),
+        bluetooth::SECRET_KEY => Some("secret key"),
+        bluetooth::DEVICE_NAME => Some("name"),
+        bluetooth::TEMPERATURE => Some("temperature"),
+        bluetooth::SETTINGS => Some("settings"),
+        bluetooth::SCHEDULE_1 => Some("schedule 1"),
+        bluetooth::SCHEDULE_2 => Some("schedule 2"),
+        bluetooth::SCHEDULE_3 => Some("schedule 3"),
+        bluetooth::TIME => Some("time"),
+        bluetooth::MANUFACTURER_NAME => Some("manufacturer name"),
+        bluetooth::MODEL_NUMBER => Some("model number"),
+        bluetooth::FIRMWARE_REVISION => Some("firmware revision"),
+        bluetooth::HARDWARE_REVISION => Some("hardware revision"),
+        bluetooth::SOFTWARE_REVISION => Some("software revision"),
+        _ => None,
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<String>>().join(" ")
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    let digits: Vec<char> = hex.chars().filter(|c| !c.is_whitespace() && *c != ':').collect();
+    if digits.len() % 2 != 0 {
+        panic!("Expected an even number of hex digits. Got {}.", hex);
+    }
+    // from_str_radix would also accept a sign, like +f
+    if !digits.iter().all(|c| c.is_ascii_hexdigit()) {
+        panic!("Cannot parse hex data {}", hex);
+    }
+    digits.chunks(2)
+        .map(|pair| {
+            let pair: String = pair.iter().collect();
+            u8::from_str_radix(&pair, 16).unwrap()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::arguments;
+
+    #[test]
+    fn it_can_convert_to_and_from_hex() {
+        assert_eq!("00 1f ff", to_hex(&[0u8, 31, 255]));
+        assert_eq!(vec![0u8, 31, 255], from_hex("001FfF"));
+        assert_eq!(vec![0u8, 31, 255], from_hex("00 1f:ff"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected an even number of hex digits. Got 123.")]
+    fn it_panics_on_odd_number_of_hex_digits() {
+        from_hex("123");
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot parse hex data +f")]
+    fn it_panics_on_signs_in_hex_data() {
+        from_hex("+f");
+    }
+
+    #[test]
+    fn it_knows_the_time_characteristic() {
+        assert_eq!(Some("time"), known_name(bluetooth::TIME));
+    }
+
+    #[test]
+    fn it_can_parse_write() {
+        let (raw_write, remaining_arguments) = parse_write(arguments(&["0:04:2F:06:24:D1", "10020005-2749-0001-0000-00805F9B042F", "2a2b", "--encrypt", "--attempts", "2", "--confirm"]));
+
+        assert_eq!(RawWrite {
+            uuid: "10020005-2749-0001-0000-00805f9b042f".to_string(),
+            data: vec![0x2a, 0x2b],
+            encrypt: true,
+            confirmed: true,
+        }, raw_write);
+        assert_eq!(arguments(&["0:04:2F:06:24:D1", "--attempts", "2"]), remaining_arguments);
+    }
+
+    #[test]
+    fn it_does_not_write_without_confirmation() {
+        let (raw_write, _) = parse_write(arguments(&["0:04:2F:06:24:D1", "10020005-2749-0001-0000-00805f9b042f", "2a2b"]));
+
+        assert!(!raw_write.confirmed);
+    }
+}
@@ -1,25 +1,80 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use chrono::Utc;
 
 use crate::bluetooth;
-use crate::bluetooth::ConnectedBluetoothPeripheral;
-use crate::models::thermostats::{Thermostats, Thermostat};
+use crate::bluetooth::{AdapterSelector, ConnectedBluetoothPeripheral, PairingProgress, RetryPolicy};
+use crate::commands::batch;
+use crate::commands::connection_options::{self, Target};
+use crate::models::secret_check::check_secret;
+use crate::models::thermostats::{DeviceInformation, Thermostats, Thermostat};
 use crate::models::thermostat_names::*;
 
+const DEFAULT_PAIRING_TIMEOUT_SECONDS: u64 = 60;
+const PAIRING_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, PartialEq)]
+pub enum Pairing {
+    /// Ask the user to press enter once the timer button has been pressed
+    Prompt,
+    /// Keep reading the secret key until the timer button has been pressed
+    WaitForTimerButton { timeout: Duration },
+}
+
 pub fn execute(arguments: Vec<String>) {
-    if arguments.len() != 1 {
-        panic!("Expected just one parameter to read. Got {}.", arguments.len());
-    }
-    let serial = &arguments[0];
+    let (pairing, arguments) = parse_pairing(arguments);
+    let options = connection_options::parse("read", &arguments);
+    let serial = match &options.target {
+        Target::All => {
+            batch::execute_for_all(options.adapter.as_ref(), options.retry_policy, |peripheral, thermostat| {
+                let mut new_thermostat = read_from_connected_peripheral(peripheral, &thermostat.serial, Some(&thermostat.secret))?;
+                new_thermostat.queued_vacation_periods = thermostat.queued_vacation_periods.clone();
+                Ok(new_thermostat)
+            });
+            return;
+        }
+        Target::Serial(serial) => serial,
+    };
 
     let mut thermostats = Thermostats::load().unwrap();
     let secret = thermostats.get(serial).and_then(|t| { Some(&t.secret) });
 
-    let new_thermostat = read_from_thermostat(serial, secret);
+    let queued_vacation_periods = thermostats.get(serial).map(|t| t.queued_vacation_periods.clone()).unwrap_or_default();
+
+    let mut new_thermostat = match read_from_thermostat(serial, secret, &pairing, options.adapter.as_ref(), options.retry_policy) {
+        Ok(thermostat) => thermostat,
+        Err(error) => {
+            eprintln!("Could not read from {}: {}", serial, error);
+            std::process::exit(1);
+        }
+    };
+    new_thermostat.queued_vacation_periods = queued_vacation_periods;
     thermostats.push(new_thermostat);
     thermostats.save().unwrap();
 }
 
-fn read_from_thermostat(serial: &String, secret: Option<&Vec<u8>>) -> Thermostat {
+// Takes out the pairing options, leaving the connection options
+pub fn parse_pairing(arguments: Vec<String>) -> (Pairing, Vec<String>) {
+    let mut pairing = Pairing::Prompt;
+    let mut remaining_arguments = vec![];
+
+    let mut arguments = arguments.into_iter();
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--no-prompt" => pairing = Pairing::WaitForTimerButton { timeout: Duration::from_secs(DEFAULT_PAIRING_TIMEOUT_SECONDS) },
+            "--pairing-timeout" => {
+                let seconds = arguments.next().expect("Expected a number of seconds after --pairing-timeout");
+                let seconds = seconds.parse::<u64>().expect("Cannot parse supplied pairing timeout");
+                pairing = Pairing::WaitForTimerButton { timeout: Duration::from_secs(seconds) };
+            }
+            _ => remaining_arguments.push(argument),
+        }
+    }
+
+    (pairing, remaining_arguments)
+}
+
+pub fn read_from_thermostat(serial: &String, secret: Option<&Vec<u8>>, pairing: &Pairing, adapter: Option<&AdapterSelector>, retry_policy: RetryPolicy) -> btleplug::Result<Thermostat> {
     let first_connection = secret.is_none();
     if first_connection {
         eprintln!("Reading from {} for the first time...", serial);
@@ -27,14 +82,32 @@ fn read_from_thermostat(serial: &String, secret: Option<&Vec<u8>>) -> Thermostat
         eprintln!("Reading from {}...", serial);
     }
 
-    let connected_peripheral = bluetooth::connect(|name| { is_thermostat_name(name) && &stripped_name(name) == serial }, first_connection).unwrap();
+    let prompt_for_timer_button = first_connection && *pairing == Pairing::Prompt;
+    let connected_peripheral = bluetooth::connect(adapter, |name| { is_thermostat_name(name) && &stripped_name(name) == serial }, prompt_for_timer_button, retry_policy)?;
 
-    let result = read_from_connected_peripheral(&connected_peripheral, serial, secret);
-    connected_peripheral.disconnect();
+    let result = match (secret, pairing) {
+        (None, Pairing::WaitForTimerButton { timeout }) => {
+            eprintln!("This is the first time you connect to this thermostat, so we need to fetch the secret key.");
+            eprintln!("Please click the timer button on the thermostat within {} seconds.", timeout.as_secs());
+            connected_peripheral.wait_for_secret_key(*timeout, PAIRING_POLL_INTERVAL, print_pairing_progress)
+                .and_then(|secret| read_from_connected_peripheral(&connected_peripheral, serial, Some(&secret)))
+        }
+        _ => read_from_connected_peripheral(&connected_peripheral, serial, secret),
+    };
+    if let Err(error) = connected_peripheral.disconnect() {
+        warn!("Could not disconnect from {}: {}", serial, error);
+    }
     result
 }
 
-pub fn read_from_connected_peripheral(peripheral: &ConnectedBluetoothPeripheral, serial: &String, secret: Option<&Vec<u8>>) -> Thermostat {
+fn print_pairing_progress(progress: PairingProgress) {
+    match progress {
+        PairingProgress::WaitingForTimerButton { .. } => eprint!("."),
+        PairingProgress::SecretKeyReleased => eprintln!("Got the secret key"),
+    }
+}
+
+pub fn read_from_connected_peripheral(peripheral: &ConnectedBluetoothPeripheral, serial: &String, secret: Option<&Vec<u8>>) -> btleplug::Result<Thermostat> {
     let mut characteristics_to_read = HashSet::new();
 
     if secret.is_none() {
@@ -48,8 +121,15 @@ pub fn read_from_connected_peripheral(peripheral: &ConnectedBluetoothPeripheral,
     characteristics_to_read.insert(bluetooth::SCHEDULE_1.to_string());
     characteristics_to_read.insert(bluetooth::SCHEDULE_2.to_string());
     characteristics_to_read.insert(bluetooth::SCHEDULE_3.to_string());
+    characteristics_to_read.insert(bluetooth::TIME.to_string());
+    characteristics_to_read.insert(bluetooth::MANUFACTURER_NAME.to_string());
+    characteristics_to_read.insert(bluetooth::MODEL_NUMBER.to_string());
+    characteristics_to_read.insert(bluetooth::FIRMWARE_REVISION.to_string());
+    characteristics_to_read.insert(bluetooth::HARDWARE_REVISION.to_string());
+    characteristics_to_read.insert(bluetooth::SOFTWARE_REVISION.to_string());
 
-    let characteristic_values = peripheral.read_characteristics(characteristics_to_read).unwrap();
+    let characteristic_values = peripheral.read_characteristics(characteristics_to_read)?;
+    let read_at = Utc::now().timestamp();
 
     let secret = match secret {
         Some(s) => s.clone(),
@@ -62,8 +142,12 @@ pub fn read_from_connected_peripheral(peripheral: &ConnectedBluetoothPeripheral,
     let schedule_1 = characteristic_values.get(&bluetooth::SCHEDULE_1.to_string()).unwrap().clone();
     let schedule_2 = characteristic_values.get(&bluetooth::SCHEDULE_2.to_string()).unwrap().clone();
     let schedule_3 = characteristic_values.get(&bluetooth::SCHEDULE_3.to_string()).unwrap().clone();
+    // Not all firmware versions may have the time characteristic
+    let time = characteristic_values.get(bluetooth::TIME).cloned().unwrap_or_default();
+    let time_read_at = if time.is_empty() { None } else { Some(read_at) };
+    let device_information = device_information(&characteristic_values);
 
-    Thermostat {
+    let mut thermostat = Thermostat {
         serial: serial.clone(),
         secret,
         name,
@@ -73,7 +157,72 @@ pub fn read_from_connected_peripheral(peripheral: &ConnectedBluetoothPeripheral,
         schedule_1,
         schedule_2,
         schedule_3,
+        time,
+        time_read_at,
+        device_information,
 
         ..Default::default()
+    };
+    if let Err(invalid_secret) = check_secret(&thermostat) {
+        eprintln!("{}: {}", serial, invalid_secret);
+        eprintln!("The thermostat has probably been reset. Pair with it again using: eco2 repair {}", serial);
+        thermostat.needs_repairing = true;
     }
-}
\ No newline at end of file
+    Ok(thermostat)
+}
+fn device_information(characteristic_values: &HashMap<String, Vec<u8>>) -> DeviceInformation {
+    let string_value = |characteristic: &str| {
+        characteristic_values.get(characteristic).map(|value| {
+            String::from_utf8_lossy(value).trim_end_matches('\0').trim().to_string()
+        })
+    };
+    DeviceInformation {
+        manufacturer_name: string_value(bluetooth::MANUFACTURER_NAME),
+        model_number: string_value(bluetooth::MODEL_NUMBER),
+        firmware_revision: string_value(bluetooth::FIRMWARE_REVISION),
+        hardware_revision: string_value(bluetooth::HARDWARE_REVISION),
+        software_revision: string_value(bluetooth::SOFTWARE_REVISION),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::arguments;
+
+    #[test]
+    fn it_prompts_for_timer_button_by_default() {
+        let (pairing, remaining_arguments) = parse_pairing(arguments(&["0:04:2F:06:24:D1", "--attempts", "2"]));
+
+        assert_eq!(Pairing::Prompt, pairing);
+        assert_eq!(arguments(&["0:04:2F:06:24:D1", "--attempts", "2"]), remaining_arguments);
+    }
+
+    #[test]
+    fn it_can_wait_for_timer_button_without_prompting() {
+        let (pairing, remaining_arguments) = parse_pairing(arguments(&["--no-prompt", "0:04:2F:06:24:D1"]));
+
+        assert_eq!(Pairing::WaitForTimerButton { timeout: Duration::from_secs(60) }, pairing);
+        assert_eq!(arguments(&["0:04:2F:06:24:D1"]), remaining_arguments);
+    }
+
+    #[test]
+    fn it_can_parse_pairing_timeout() {
+        let (pairing, _) = parse_pairing(arguments(&["0:04:2F:06:24:D1", "--pairing-timeout", "30"]));
+
+        assert_eq!(Pairing::WaitForTimerButton { timeout: Duration::from_secs(30) }, pairing);
+    }
+
+    #[test]
+    fn it_decodes_device_information() {
+        let mut characteristic_values = HashMap::new();
+        characteristic_values.insert(bluetooth::MODEL_NUMBER.to_string(), b"eTRV\0\0".to_vec());
+        characteristic_values.insert(bluetooth::FIRMWARE_REVISION.to_string(), b"0.7.2".to_vec());
+
+        assert_eq!(DeviceInformation {
+            model_number: Some("eTRV".to_string()),
+            firmware_revision: Some("0.7.2".to_string()),
+            ..Default::default()
+        }, device_information(&characteristic_values));
+    }
+}
@@ -0,0 +1,37 @@
+use crate::commands::connection_options::{self, Target};
+use crate::commands::read::{parse_pairing, read_from_thermostat};
+use crate::models::thermostats::Thermostats;
+
+pub fn execute(arguments: Vec<String>) {
+    let (pairing, arguments) = parse_pairing(arguments);
+    let options = connection_options::parse("repair", &arguments);
+    let serial = match &options.target {
+        Target::All => panic!("Thermostats can only be paired one at a time. Expected a thermostat serial to repair."),
+        Target::Serial(serial) => serial,
+    };
+
+    let mut thermostats = Thermostats::load().unwrap();
+    let queued_vacation_periods = match thermostats.get(serial) {
+        Some(thermostat) => thermostat.queued_vacation_periods.clone(),
+        None => {
+            eprintln!("Unknown thermostat with serial {}. Use read to pair with a new thermostat.", serial);
+            std::process::exit(1);
+        }
+    };
+
+    // Ignore the stored secret, so it is fetched from the thermostat again
+    let mut new_thermostat = match read_from_thermostat(serial, None, &pairing, options.adapter.as_ref(), options.retry_policy) {
+        Ok(thermostat) => thermostat,
+        Err(error) => {
+            eprintln!("Could not repair {}: {}", serial, error);
+            std::process::exit(1);
+        }
+    };
+    if new_thermostat.needs_repairing {
+        eprintln!("The new secret key does not match {} either.", serial);
+        std::process::exit(1);
+    }
+    new_thermostat.queued_vacation_periods = queued_vacation_periods;
+    thermostats.push(new_thermostat);
+    thermostats.save().unwrap();
+}
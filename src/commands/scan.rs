@@ -1,26 +1,195 @@
+use std::convert::TryFrom;
 use std::time::Duration;
+use serde::Serialize;
 
-use crate::bluetooth::scan;
+use crate::bluetooth::{scan, AdapterSelector, ScannedBluetoothPeripheral};
+use crate::commands::connection_options::{adapter_or_default, parse_adapter};
+use crate::models::parsed_thermostat::ParsedThermostat;
 use crate::models::thermostat_names::*;
+use crate::models::thermostats::Thermostats;
+
+const DEFAULT_TIMEOUT_SECONDS: u64 = 120;
+
+#[derive(Debug, PartialEq)]
+struct ScanArguments {
+    timeout: Duration,
+    count: Option<usize>,
+    json: bool,
+    adapter: Option<AdapterSelector>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct ScannedThermostat {
+    serial: String,
+    address: String,
+    rssi: Option<i8>,
+    leading_digit: u8,
+    paired: bool,
+    name: Option<String>,
+}
+
+impl ScannedThermostat {
+    fn new(peripheral: &ScannedBluetoothPeripheral, thermostats: &Thermostats) -> Self {
+        let serial = stripped_name(&peripheral.name);
+        let thermostat = thermostats.get(&serial);
+        Self {
+            address: peripheral.address.clone(),
+            rssi: peripheral.rssi,
+            leading_digit: leading_digit(&peripheral.name),
+            paired: thermostat.is_some(),
+            name: thermostat.and_then(|t| ParsedThermostat::try_from(t).ok()).map(|t| t.name),
+            serial,
+        }
+    }
+
+    fn table_row(&self) -> String {
+        let rssi = match self.rssi {
+            Some(rssi) => format!("{} dBm", rssi),
+            None => "-".to_string(),
+        };
+        let paired = if self.paired { "yes" } else { "no" };
+        let name = self.name.as_deref().unwrap_or("-");
+        format!("{:<18} {:<18} {:>8} {:>5} {:<6} {}", self.serial, self.address, rssi, self.leading_digit, paired, name)
+    }
+}
 
 pub fn execute(arguments: Vec<String>) {
-    if arguments.len() != 0 {
-        panic!("Expected no parameters to scan. Got {}.", arguments.len());
+    let arguments = parse_arguments(&arguments);
+    let thermostats = Thermostats::load().expect("Could not read thermostat data");
+
+    match arguments.count {
+        Some(count) => eprintln!("Scanning for {} seconds, or until {} thermostats are found. Please wait.", arguments.timeout.as_secs(), count),
+        None => eprintln!("Scanning for {} seconds. Please wait.", arguments.timeout.as_secs()),
     }
 
-    eprintln!("Scanning for 2 minutes. Please wait.");
-    let peripherals = scan(Duration::from_secs(120));
+    if !arguments.json {
+        println!("{:<18} {:<18} {:>8} {:>5} {:<6} NAME", "SERIAL", "ADDRESS", "RSSI", "DIGIT", "PAIRED");
+    }
 
-    let mut peripheral_found = false;
-    for peripheral in peripherals.iter() {
+    // Print table rows as soon as thermostats are found, but collect the whole
+    // result before writing JSON, so the output is a single valid document.
+    let mut scanned_thermostats = vec![];
+    let adapter = adapter_or_default(arguments.adapter.clone());
+    scan(adapter.as_ref(), arguments.timeout, |peripheral| {
         if is_thermostat_name(&peripheral.name) {
-            println!("{}", stripped_name(&peripheral.name));
-            peripheral_found = true
+            let scanned_thermostat = ScannedThermostat::new(peripheral, &thermostats);
+            if !arguments.json {
+                println!("{}", scanned_thermostat.table_row());
+            }
+            scanned_thermostats.push(scanned_thermostat);
         }
+        arguments.count.map_or(true, |count| scanned_thermostats.len() < count)
+    }).expect("Could not scan for thermostats");
+
+    if arguments.json {
+        println!("{}", serde_json::to_string_pretty(&scanned_thermostats).unwrap());
     }
 
-    if !peripheral_found {
+    if scanned_thermostats.is_empty() {
         eprintln!("No thermostats found");
         std::process::exit(1)
     }
 }
+
+fn parse_arguments(arguments: &[String]) -> ScanArguments {
+    let mut result = ScanArguments {
+        timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECONDS),
+        count: None,
+        json: false,
+        adapter: None,
+    };
+
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--timeout" => {
+                let seconds = arguments.next().expect("Expected a number of seconds after --timeout");
+                let seconds = seconds.parse::<u64>().expect("Cannot parse supplied timeout");
+                result.timeout = Duration::from_secs(seconds);
+            }
+            "--count" => {
+                let number = arguments.next().expect("Expected a number of thermostats after --count");
+                result.count = Some(number.parse::<usize>().expect("Cannot parse supplied count"));
+            }
+            "--json" => result.json = true,
+            "--adapter" => result.adapter = Some(parse_adapter(arguments.next())),
+            _ => panic!("Unknown argument to scan: {}. Expected --timeout, --count, --json, or --adapter.", argument),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::create_thermostat;
+
+    #[test]
+    fn it_scans_for_two_minutes_by_default() {
+        let expected_arguments = ScanArguments {
+            timeout: Duration::from_secs(120),
+            count: None,
+            json: false,
+            adapter: None,
+        };
+        assert_eq!(expected_arguments, parse_arguments(&[]));
+    }
+
+    #[test]
+    fn it_can_parse_timeout_count_json_and_adapter() {
+        let arguments = vec!["--timeout".to_string(), "30".to_string(), "--count".to_string(), "4".to_string(), "--json".to_string(), "--adapter".to_string(), "1".to_string()];
+        let expected_arguments = ScanArguments {
+            timeout: Duration::from_secs(30),
+            count: Some(4),
+            json: true,
+            adapter: Some(AdapterSelector::Index(1)),
+        };
+        assert_eq!(expected_arguments, parse_arguments(&arguments));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown argument to scan: --foo. Expected --timeout, --count, --json, or --adapter.")]
+    fn it_panics_on_unknown_argument() {
+        parse_arguments(&["--foo".to_string()]);
+    }
+
+    #[test]
+    fn it_knows_about_paired_thermostats() {
+        let thermostats = Thermostats {
+            thermostats: vec![create_thermostat()],
+        };
+        let peripheral = ScannedBluetoothPeripheral {
+            name: "2;0:04:2F:06:24:D1;eTRV".to_string(),
+            address: "00:04:2F:06:24:D1".to_string(),
+            rssi: Some(-67),
+        };
+
+        let scanned_thermostat = ScannedThermostat::new(&peripheral, &thermostats);
+
+        assert_eq!(ScannedThermostat {
+            serial: "0:04:2F:06:24:D1".to_string(),
+            address: "00:04:2F:06:24:D1".to_string(),
+            rssi: Some(-67),
+            leading_digit: 2,
+            paired: true,
+            name: Some("Alrum opgang".to_string()),
+        }, scanned_thermostat);
+    }
+
+    #[test]
+    fn it_knows_about_unpaired_thermostats() {
+        let thermostats = Thermostats { thermostats: vec![] };
+        let peripheral = ScannedBluetoothPeripheral {
+            name: "0;0:04:2F:C0:F2:58;eTRV".to_string(),
+            address: "00:04:2F:C0:F2:58".to_string(),
+            rssi: None,
+        };
+
+        let scanned_thermostat = ScannedThermostat::new(&peripheral, &thermostats);
+
+        assert!(!scanned_thermostat.paired);
+        assert_eq!(None, scanned_thermostat.name);
+        assert_eq!("0:04:2F:C0:F2:58   00:04:2F:C0:F2:58         -     0 no     -", scanned_thermostat.table_row());
+    }
+}
@@ -1,11 +1,25 @@
+use std::convert::TryFrom;
+
+use std::fmt;
+
+use crate::commands::{time_zone_option, unit_option, vacation_period};
+use crate::models::parsed_thermostat::{ScheduleMode, Temperature};
+use crate::models::temperature_unit::TemperatureUnit;
 use crate::models::thermostats::{Thermostats, Thermostat};
+use crate::models::time_zone::TimeZoneSetting;
 use chrono::prelude::*;
 
 const SET_POINT_TEMPERATURE: &str = "set-point-temperature";
 const VACATION_PERIOD: &str = "vacation-period";
+const QUEUE_VACATION_PERIOD: &str = "queue-vacation-period";
 const SCHEDULE_MODE: &str = "schedule-mode";
+const MINIMUM_TEMPERATURE: &str = "minimum-temperature";
+const MAXIMUM_TEMPERATURE: &str = "maximum-temperature";
+const TIME: &str = "time";
 
 pub fn execute(arguments: Vec<String>) {
+    let (unit, arguments) = unit_option::take(arguments);
+    let (time_zone, arguments) = time_zone_option::take(arguments);
     if arguments.len() < 3 {
         panic!(
           "Expected at least three arguments: The thermostat serial, the property to set, and the value(s). Got {} arguments.",
@@ -25,48 +39,98 @@ pub fn execute(arguments: Vec<String>) {
     let property = &arguments[1][..];
     let remaining_arguments = &arguments[2..];
     match property {
-        SET_POINT_TEMPERATURE => set_set_point_temperature(&mut thermostat, remaining_arguments),
-        VACATION_PERIOD => set_vacation_period(&mut thermostat, remaining_arguments),
+        SET_POINT_TEMPERATURE => set_set_point_temperature(&mut thermostat, remaining_arguments, unit),
+        VACATION_PERIOD => set_vacation_period(&mut thermostat, remaining_arguments, time_zone),
+        QUEUE_VACATION_PERIOD => queue_vacation_period(&mut thermostat, remaining_arguments, time_zone),
         SCHEDULE_MODE => set_schedule_mode(&mut thermostat, remaining_arguments),
-        _ => panic!("Unknown property: {}. Expected one of set-point-temperature, vacation-period, and schedule-mode", property),
+        MINIMUM_TEMPERATURE => thermostat.new_device_settings.minimum_temperature = Some(parse_temperature(MINIMUM_TEMPERATURE, remaining_arguments, unit)),
+        MAXIMUM_TEMPERATURE => thermostat.new_device_settings.maximum_temperature = Some(parse_temperature(MAXIMUM_TEMPERATURE, remaining_arguments, unit)),
+        TIME => set_time(&mut thermostat, remaining_arguments),
+        _ => panic!("Unknown property: {}. Expected one of set-point-temperature, vacation-period, queue-vacation-period, schedule-mode, minimum-temperature, maximum-temperature, and time", property),
     }
 
     thermostats.push(thermostat);
     thermostats.save().unwrap();
 }
 
-fn set_set_point_temperature(thermostat: &mut Thermostat, arguments: &[String]) {
+fn set_set_point_temperature(thermostat: &mut Thermostat, arguments: &[String], unit: TemperatureUnit) {
     if arguments.len() != 1 {
         panic!("Expected just one argument as set-point temperature, got {}", arguments.len());
     }
     let new_set_point_temperature = arguments[0].parse::<f32>().expect("Cannot parse supplied set-point temperature");
 
-    thermostat.new_set_point_temperature = Some(new_set_point_temperature);
+    thermostat.new_set_point_temperature = Some(valid_temperature(new_set_point_temperature, unit));
+}
+
+fn set_vacation_period(thermostat: &mut Thermostat, arguments: &[String], time_zone: TimeZoneSetting) {
+    if arguments.len() == 1 && arguments[0] == "clear" {
+        thermostat.new_vacation_period = Some((0, 0));
+        return;
+    }
+
+    let (start, end) = parse_vacation_period(arguments, time_zone);
+    println!("Vacation: {} - {}", time_zone.format(start), time_zone.format(end));
+
+    thermostat.new_vacation_period = Some((start.timestamp(), end.timestamp()));
 }
 
-fn set_vacation_period(thermostat: &mut Thermostat, arguments: &[String]) {
-    // Assume that we want to clear the vacation period if we only have one argument.
-    // Is this a bit too hacky?
-    if arguments.len() == 1 {
-        clear_vacation_period(thermostat, arguments);
+fn queue_vacation_period(thermostat: &mut Thermostat, arguments: &[String], time_zone: TimeZoneSetting) {
+    if arguments.len() == 1 && arguments[0] == "clear" {
+        thermostat.queued_vacation_periods.clear();
         return;
     }
 
-    if arguments.len() != 2 {
-        panic!("Expected two arguments as vacation period, got {}", arguments.len());
+    let (start, end) = parse_vacation_period(arguments, time_zone);
+    if let Err((overlapping_start, overlapping_end)) = thermostat.queue_vacation_period(start.timestamp(), end.timestamp()) {
+        panic!(
+            "The vacation period overlaps the queued vacation period {} - {}",
+            time_zone.format(Utc.timestamp(overlapping_start, 0)),
+            time_zone.format(Utc.timestamp(overlapping_end, 0))
+        );
+    }
+    for (queued_start, queued_end) in &thermostat.queued_vacation_periods {
+        println!("Queued vacation: {} - {}", time_zone.format(Utc.timestamp(*queued_start, 0)), time_zone.format(Utc.timestamp(*queued_end, 0)));
+    }
+}
+
+fn parse_vacation_period(arguments: &[String], time_zone: TimeZoneSetting) -> (DateTime<Utc>, DateTime<Utc>) {
+    match time_zone {
+        TimeZoneSetting::Local => vacation_period_in(arguments, Local::now()),
+        TimeZoneSetting::Named(tz) => vacation_period_in(arguments, Utc::now().with_timezone(&tz)),
     }
-    let new_vacation_period_start = parse_date_time(&arguments[0]);
-    let new_vacation_period_end = parse_date_time(&arguments[1]);
+}
 
-    thermostat.new_vacation_period = Some((new_vacation_period_start, new_vacation_period_end));
+// Whole hours are rounded in the time zone the vacation is entered in, which
+// matters for time zones with a UTC offset of e.g. +05:30
+fn vacation_period_in<Tz: TimeZone>(arguments: &[String], now: DateTime<Tz>) -> (DateTime<Utc>, DateTime<Utc>)
+where
+    Tz::Offset: fmt::Display,
+{
+    let (start, end) = vacation_period::parse(arguments, &now);
+    let start = whole_hour(&start);
+    let end = whole_hour(&end);
+    if end <= start {
+        panic!("The vacation period must be at least an hour long");
+    }
+    (start.with_timezone(&Utc), end.with_timezone(&Utc))
 }
 
-fn clear_vacation_period(thermostat: &mut Thermostat, arguments: &[String]) {
-    if arguments[0] != "clear" {
-        panic!("Expected either a start and end date, or just 'clear'");
+fn whole_hour<Tz: TimeZone>(time: &DateTime<Tz>) -> DateTime<Tz>
+where
+    Tz::Offset: fmt::Display,
+{
+    let rounded_time = vacation_period::round_to_whole_hour(time);
+    if rounded_time.timestamp() != time.timestamp() {
+        eprintln!("The thermostat only supports whole hours. Rounding {} to {}", formatted_time(time), formatted_time(&rounded_time));
     }
+    rounded_time
+}
 
-    thermostat.new_vacation_period = Some((0, 0));
+fn formatted_time<Tz: TimeZone>(time: &DateTime<Tz>) -> String
+where
+    Tz::Offset: fmt::Display,
+{
+    time.format("%Y-%m-%d %H:%M").to_string()
 }
 
 fn set_schedule_mode(thermostat: &mut Thermostat, arguments: &[String]) {
@@ -77,46 +141,147 @@ fn set_schedule_mode(thermostat: &mut Thermostat, arguments: &[String]) {
     thermostat.new_schedule_mode = Some(parse_schedule_mode(&arguments[0]));
 }
 
-fn parse_date_time(arg: &str) -> i64 {
-    let parsed_date_time = Local.datetime_from_str(arg, "%Y-%m-%d %H:%M").expect("Could not parse date time. Should be in format YYYY-mm-dd HH:MM");
-    let minutes = parsed_date_time.minute();
-    if minutes != 0 {
-        panic!("Only minutes of 00 are supported, got {:02}: {}", minutes, arg);
+// The time is taken when syncing, as that is when it is written
+fn set_time(thermostat: &mut Thermostat, arguments: &[String]) {
+    if arguments.len() != 1 || arguments[0] != "now" {
+        panic!("Expected 'now' as time");
     }
-    parsed_date_time.timestamp()
+
+    thermostat.new_time = true;
+}
+
+fn parse_temperature(property: &str, arguments: &[String], unit: TemperatureUnit) -> f32 {
+    if arguments.len() != 1 {
+        panic!("Expected just one argument as {}, got {}", property, arguments.len());
+    }
+    let temperature = arguments[0].parse::<f32>().unwrap_or_else(|_| panic!("Cannot parse supplied {}", property));
+    valid_temperature(temperature, unit)
+}
+
+// The temperature in degrees Celcius that will be written to the thermostat
+fn valid_temperature(degrees: f32, unit: TemperatureUnit) -> f32 {
+    let degrees_celcius = unit.to_celsius(degrees);
+    let temperature = match Temperature::try_from(degrees_celcius) {
+        Ok(temperature) => temperature,
+        Err(_) => panic!(
+            "{} is outside the range of the thermostat, {} to {}",
+            unit.format_degrees(degrees_celcius),
+            unit.format_degrees(Temperature::MINIMUM_DEGREES_CELCIUS),
+            unit.format_degrees(Temperature::MAXIMUM_DEGREES_CELCIUS)
+        ),
+    };
+    if temperature.in_degrees_celcius() != degrees_celcius {
+        eprintln!("The thermostat only supports half degrees Celsius. Rounding {} to {}", unit.format_degrees(degrees_celcius), unit.format(temperature));
+    }
+    temperature.in_degrees_celcius()
 }
 
 fn parse_schedule_mode(arg: &str) -> u8 {
-    match arg {
-        "manual" => 0,
-        "scheduled" => 1,
-        "vacation" => 3,
-        _ => panic!("Unknown schedule mode: {}. Expected one of manual, scheduled, and vacation", arg)
+    match ScheduleMode::parse(arg) {
+        Some(schedule_mode) => schedule_mode.to_byte(),
+        None => panic!("Unknown schedule mode: {}. Expected one of manual, scheduled, vacation, or a number from 0 to {}", arg, ScheduleMode::MAX_VALUE)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::models::parsed_thermostat::{ParsedThermostat, ScheduleMode};
+    use crate::models::parsed_thermostat::ParsedThermostat;
 
     #[test]
-    fn it_can_parse_vacation_date() {
-        let parsed_timestamp = parse_date_time("2021-05-24 13:00");
-        let expected_timestamp = Local.ymd(2021, 5, 24).and_hms(13, 0, 0).timestamp();
-        assert_eq!(parsed_timestamp, expected_timestamp);
+    fn it_stores_vacation_period_in_whole_hours() {
+        let mut thermostat = Thermostat::default();
+        let arguments = vec!["2021-05-24 13:07".to_string(), "2021-05-30 09:45".to_string()];
+
+        set_vacation_period(&mut thermostat, &arguments, TimeZoneSetting::Local);
+
+        let expected_start = Local.ymd(2021, 5, 24).and_hms(13, 0, 0).timestamp();
+        let expected_end = Local.ymd(2021, 5, 30).and_hms(10, 0, 0).timestamp();
+        assert_eq!(Some((expected_start, expected_end)), thermostat.new_vacation_period);
     }
 
     #[test]
-    #[should_panic(expected = "Only minutes of 00 are supported, got 07: 2021-05-24 13:07")]
-    fn it_disallows_specifying_minutes_in_vacation_date() {
-        parse_date_time("2021-05-24 13:07");
+    fn it_can_clear_vacation_period() {
+        let mut thermostat = Thermostat::default();
+
+        set_vacation_period(&mut thermostat, &["clear".to_string()], TimeZoneSetting::Local);
+
+        assert_eq!(Some((0, 0)), thermostat.new_vacation_period);
     }
 
     #[test]
-    #[should_panic(expected = "Could not parse date time. Should be in format YYYY-mm-dd HH:MM")]
-    fn it_panics_when_given_invalid_date_format() {
-        parse_date_time("24/5 2021 13:07");
+    fn it_can_queue_and_clear_vacation_periods() {
+        let mut thermostat = Thermostat::default();
+        let time_zone = TimeZoneSetting::Named(chrono_tz::Europe::Copenhagen);
+
+        queue_vacation_period(&mut thermostat, &["2026-12-21".to_string(), "2026-12-28".to_string()], time_zone);
+        queue_vacation_period(&mut thermostat, &["2026-11-02".to_string(), "for 1 week".to_string()], time_zone);
+
+        let expected_periods = vec![
+            (Utc.ymd(2026, 11, 1).and_hms(23, 0, 0).timestamp(), Utc.ymd(2026, 11, 8).and_hms(23, 0, 0).timestamp()),
+            (Utc.ymd(2026, 12, 20).and_hms(23, 0, 0).timestamp(), Utc.ymd(2026, 12, 27).and_hms(23, 0, 0).timestamp()),
+        ];
+        assert_eq!(expected_periods, thermostat.queued_vacation_periods);
+        assert_eq!(None, thermostat.new_vacation_period);
+
+        queue_vacation_period(&mut thermostat, &["clear".to_string()], time_zone);
+        assert!(thermostat.queued_vacation_periods.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "The vacation period overlaps the queued vacation period 2026-12-21 00:00 - 2026-12-28 00:00")]
+    fn it_panics_on_overlapping_queued_vacation_periods() {
+        let mut thermostat = Thermostat::default();
+        let time_zone = TimeZoneSetting::Named(chrono_tz::Europe::Copenhagen);
+
+        queue_vacation_period(&mut thermostat, &["2026-12-21".to_string(), "2026-12-28".to_string()], time_zone);
+        queue_vacation_period(&mut thermostat, &["2026-12-27".to_string(), "2027-01-02".to_string()], time_zone);
+    }
+
+    #[test]
+    fn it_stores_vacation_period_in_the_given_time_zone() {
+        let mut thermostat = Thermostat::default();
+        let arguments = vec!["2026-10-24 12:00".to_string(), "2026-10-26 12:00".to_string()];
+
+        set_vacation_period(&mut thermostat, &arguments, TimeZoneSetting::Named(chrono_tz::Europe::Copenhagen));
+
+        let expected_start = Utc.ymd(2026, 10, 24).and_hms(10, 0, 0).timestamp();
+        let expected_end = Utc.ymd(2026, 10, 26).and_hms(11, 0, 0).timestamp();
+        assert_eq!(Some((expected_start, expected_end)), thermostat.new_vacation_period);
+    }
+
+    #[test]
+    fn it_rounds_to_whole_hours_in_the_given_time_zone() {
+        let now = Utc.ymd(2026, 10, 19).and_hms(10, 0, 0).with_timezone(&chrono_tz::Asia::Kolkata);
+        let arguments = vec!["2026-10-24 12:10".to_string(), "+1d".to_string()];
+
+        let (start, _) = vacation_period_in(&arguments, now);
+
+        assert_eq!(Utc.ymd(2026, 10, 24).and_hms(6, 30, 0), start);
+    }
+
+    #[test]
+    fn it_rounds_temperature_to_half_degrees() {
+        assert_eq!(21.5, valid_temperature(21.3, TemperatureUnit::Celsius));
+        assert_eq!(21.0, valid_temperature(21.0, TemperatureUnit::Celsius));
+    }
+
+    #[test]
+    fn it_converts_fahrenheit_to_half_degrees_celsius() {
+        assert_eq!(21.0, valid_temperature(70.0, TemperatureUnit::Fahrenheit));
+        assert_eq!(5.0, valid_temperature(41.0, TemperatureUnit::Fahrenheit));
+    }
+
+    #[test]
+    #[should_panic(expected = "86°F is outside the range of the thermostat, 41°F to 82.4°F")]
+    fn it_panics_on_fahrenheit_outside_range() {
+        valid_temperature(86.0, TemperatureUnit::Fahrenheit);
+    }
+
+    #[test]
+    #[should_panic(expected = "-5°C is outside the range of the thermostat, 5°C to 28°C")]
+    fn it_panics_on_temperature_outside_range() {
+        valid_temperature(-5.0, TemperatureUnit::Celsius);
     }
 
     #[test]
@@ -124,11 +289,18 @@ mod tests {
         assert_eq!(ParsedThermostat::parse_schedule_mode(parse_schedule_mode("manual")), ScheduleMode::Manual);
         assert_eq!(ParsedThermostat::parse_schedule_mode(parse_schedule_mode("scheduled")), ScheduleMode::Scheduled);
         assert_eq!(ParsedThermostat::parse_schedule_mode(parse_schedule_mode("vacation")), ScheduleMode::Vacation);
+        assert_eq!(parse_schedule_mode("2"), 2);
     }
 
     #[test]
-    #[should_panic(expected = "Unknown schedule mode: abc. Expected one of manual, scheduled, and vacation")]
+    #[should_panic(expected = "Unknown schedule mode: abc. Expected one of manual, scheduled, vacation, or a number from 0 to 7")]
     fn it_panics_on_unknown_schedule_mode() {
         parse_schedule_mode("abc");
     }
+
+    #[test]
+    #[should_panic(expected = "Unknown schedule mode: 9. Expected one of manual, scheduled, vacation, or a number from 0 to 7")]
+    fn it_panics_on_schedule_mode_the_secret_check_would_reject() {
+        parse_schedule_mode("9");
+    }
 }
@@ -1,9 +1,14 @@
+use std::convert::TryFrom;
 use chrono::prelude::*;
 
+use crate::commands::{time_zone_option, unit_option};
 use crate::models::thermostats::Thermostats;
 use crate::models::parsed_thermostat::ParsedThermostat;
+use crate::models::secret_check::check_secret;
 
 pub fn execute(arguments: Vec<String>) {
+    let (unit, arguments) = unit_option::take(arguments);
+    let (time_zone, arguments) = time_zone_option::take(arguments);
     if arguments.len() != 1 {
         panic!("Expected just one parameter to show. Got {}.", arguments.len());
     }
@@ -15,19 +20,70 @@ pub fn execute(arguments: Vec<String>) {
         Some(t) => t,
     };
 
-    let parsed_thermostat = ParsedThermostat::from_thermostat(&thermostat);
+    if thermostat.needs_repairing {
+        eprintln!("The secret key of {} no longer matches the thermostat. Pair with it again using: eco2 repair {}", name, name);
+        std::process::exit(1);
+    }
+    if let Err(invalid_secret) = check_secret(&thermostat) {
+        eprintln!("{}. Pair with it again using: eco2 repair {}", invalid_secret, name);
+        std::process::exit(1);
+    }
+
+    let parsed_thermostat = match ParsedThermostat::try_from(&thermostat) {
+        Ok(parsed_thermostat) => parsed_thermostat,
+        Err(error) => {
+            eprintln!("{}. Try reading the thermostat again.", error);
+            std::process::exit(1);
+        }
+    };
 
     println!("Name: {}", parsed_thermostat.name);
     println!("{}% battery", parsed_thermostat.battery_percentage);
+    let device_information = &thermostat.device_information;
+    if let Some(manufacturer_name) = &device_information.manufacturer_name {
+        println!("Manufacturer: {}", manufacturer_name);
+    }
+    if let Some(model_number) = &device_information.model_number {
+        println!("Model: {}", model_number);
+    }
+    if let Some(firmware_revision) = &device_information.firmware_revision {
+        println!("Firmware revision: {}", firmware_revision);
+    }
+    if let Some(hardware_revision) = &device_information.hardware_revision {
+        println!("Hardware revision: {}", hardware_revision);
+    }
+    if let Some(software_revision) = &device_information.software_revision {
+        println!("Software revision: {}", software_revision);
+    }
+    if let Some(clock) = parsed_thermostat.clock {
+        let utc_offset = FixedOffset::east_opt(clock.utc_offset).unwrap_or_else(|| FixedOffset::east(0));
+        let clock_time = clock.time.with_timezone(&utc_offset).format("%Y-%m-%d %H:%M %:z");
+        match clock.drift {
+            Some(drift) => println!("Clock: {} ({})", clock_time, describe_drift(drift)),
+            None => println!("Clock: {}", clock_time),
+        }
+    }
     println!("");
-    println!("Set-point/room temperature: {} / {}", parsed_thermostat.set_point_temperature, parsed_thermostat.room_temperature);
-    println!("Vacation/frost protection temperature: {} / {}", parsed_thermostat.vacation_temperature, parsed_thermostat.frost_protection_temperature);
+    println!("Set-point/room temperature: {} / {}", unit.format(parsed_thermostat.set_point_temperature), unit.format(parsed_thermostat.room_temperature));
+    println!("Vacation/frost protection temperature: {} / {}", unit.format(parsed_thermostat.vacation_temperature), unit.format(parsed_thermostat.frost_protection_temperature));
     println!("");
     println!("Schedule mode: {}", parsed_thermostat.schedule_mode);
     if let Some((vacation_start, vacation_end)) = parsed_thermostat.vacation_period {
-        println!("Vacation: {} - {}", formatted_date(vacation_start), formatted_date(vacation_end));
+        println!("Vacation: {} - {}", time_zone.format(vacation_start), time_zone.format(vacation_end));
+    }
+    for (queued_start, queued_end) in &thermostat.queued_vacation_periods {
+        println!("Queued vacation: {} - {}", time_zone.format(Utc.timestamp(*queued_start, 0)), time_zone.format(Utc.timestamp(*queued_end, 0)));
     }
     println!("");
+    let device_settings = parsed_thermostat.device_settings;
+    println!("Child lock: {}", on_off(device_settings.child_lock));
+    println!("Adaptive learning: {}", on_off(device_settings.adaptive_learning));
+    println!("Installation: {}", installation(device_settings.vertical_installation));
+    println!("Display flip: {}", on_off(device_settings.display_flipped));
+    println!("Slow regulation: {}", on_off(device_settings.slow_regulation));
+    println!("Valve installed: {}", if device_settings.valve_installed { "yes" } else { "no" });
+    println!("Minimum/maximum temperature: {} / {}", unit.format(device_settings.minimum_temperature), unit.format(device_settings.maximum_temperature));
+    println!("");
     println!("Daily Schedules");
     println!("Monday: {}", parsed_thermostat.schedule_monday);
     println!("Tuesday: {}", parsed_thermostat.schedule_tuesday);
@@ -37,28 +93,67 @@ pub fn execute(arguments: Vec<String>) {
     println!("Saturday: {}", parsed_thermostat.schedule_saturday);
     println!("Sunday: {}", parsed_thermostat.schedule_sunday);
 
-    if thermostat.new_set_point_temperature.is_some() || thermostat.new_vacation_period.is_some() || thermostat.new_schedule_mode.is_some() {
+    let new_device_settings = &thermostat.new_device_settings;
+    if thermostat.new_set_point_temperature.is_some() || thermostat.new_vacation_period.is_some() || thermostat.new_schedule_mode.is_some() || !new_device_settings.is_empty() || thermostat.new_time {
         println!("");
         println!("Properties to be written back to thermostat:");
 
         if let Some(new_set_point_temperature) = thermostat.new_set_point_temperature {
-            println!("Set-point temperature: {}", new_set_point_temperature);
+            println!("Set-point temperature: {}", unit.format_degrees(new_set_point_temperature));
         }
         if let Some((new_vacation_start, new_vacation_end)) = thermostat.new_vacation_period {
             if new_vacation_start == 0 {
                 println!("Reset vacation");
             } else {
-                let new_vacation_start = formatted_date(Utc.timestamp(new_vacation_start, 0));
-                let new_vacation_end = formatted_date(Utc.timestamp(new_vacation_end, 0));
+                let new_vacation_start = time_zone.format(Utc.timestamp(new_vacation_start, 0));
+                let new_vacation_end = time_zone.format(Utc.timestamp(new_vacation_end, 0));
                 println!("Vacation: {} - {}", new_vacation_start, new_vacation_end);
             }
         }
         if let Some(new_schedule_mode) = thermostat.new_schedule_mode {
             println!("Schedule mode: {}", ParsedThermostat::parse_schedule_mode(new_schedule_mode));
         }
+        if thermostat.new_time {
+            println!("Time: set to the time of this computer");
+        }
+        if let Some(minimum_temperature) = new_device_settings.minimum_temperature {
+            println!("Minimum temperature: {}", unit.format_degrees(minimum_temperature));
+        }
+        if let Some(maximum_temperature) = new_device_settings.maximum_temperature {
+            println!("Maximum temperature: {}", unit.format_degrees(maximum_temperature));
+        }
+    }
+}
+
+// A clock that is less than a minute off is good enough for schedules in
+// 30-minute increments
+fn describe_drift(drift_seconds: i64) -> String {
+    let minutes = drift_seconds.abs() / 60;
+    match (minutes, drift_seconds > 0) {
+        (0, _) => "in sync with this computer when read".to_string(),
+        (1, true) => "1 minute ahead of this computer when read".to_string(),
+        (1, false) => "1 minute behind this computer when read".to_string(),
+        (_, true) => format!("{} minutes ahead of this computer when read", minutes),
+        (_, false) => format!("{} minutes behind this computer when read", minutes),
     }
 }
 
-fn formatted_date(t: chrono::DateTime<Utc>) -> String {
-    t.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string()
+fn on_off(value: bool) -> &'static str {
+    if value { "on" } else { "off" }
+}
+
+fn installation(vertical_installation: bool) -> &'static str {
+    if vertical_installation { "vertical" } else { "horizontal" }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_describes_clock_drift_in_minutes() {
+        assert_eq!("in sync with this computer when read", describe_drift(-59));
+        assert_eq!("1 minute ahead of this computer when read", describe_drift(90));
+        assert_eq!("62 minutes behind this computer when read", describe_drift(-3720));
+    }
 }
@@ -1,68 +1,393 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Range;
+use chrono::{TimeZone, Utc};
 
 use crate::bluetooth;
+use crate::commands::batch;
+use crate::commands::connection_options::{self, Target};
+use crate::commands::time_zone_option;
 use crate::commands::read::read_from_connected_peripheral;
+use crate::encryption::decrypt;
+use crate::models::secret_check::{check_settings, check_temperature, InvalidSecret};
 use crate::models::thermostat_names::*;
 use crate::models::thermostats::{Thermostat, Thermostats};
-use crate::models::parsed_thermostat::{update_schedule_mode, update_set_point_temperature, update_vacation_period};
+use crate::models::blocks::{DecodeError, TimeBlock};
+use crate::models::parsed_thermostat::{update_device_settings, update_schedule_mode, update_set_point_temperature, update_time, update_vacation_period, ParsedThermostat, Temperature};
+use crate::models::time_zone::TimeZoneSetting;
+
+const SET_TIME: &str = "--set-time";
+// Writing and reading back the time takes a while over a flaky connection
+const MAX_CONFIRMED_TIME_DIFFERENCE_SECONDS: i64 = 60;
 
 pub fn execute(arguments: Vec<String>) {
-    if arguments.len() != 1 {
-        panic!("Expected just one parameter to sync. Got {}.", arguments.len());
-    }
-    let serial = &arguments[0];
+    let set_time = arguments.iter().any(|argument| argument == SET_TIME);
+    let arguments: Vec<String> = arguments.into_iter().filter(|argument| argument != SET_TIME).collect();
+    let (time_zone, arguments) = time_zone_option::take(arguments);
+    let options = connection_options::parse("sync", &arguments);
+    let sync = |connected_peripheral: &bluetooth::ConnectedBluetoothPeripheral, thermostat: &Thermostat| {
+        let mut thermostat = thermostat.clone();
+        thermostat.new_time |= set_time;
+        sync_connected_peripheral(connected_peripheral, &thermostat, time_zone)
+    };
+    let serial = match &options.target {
+        Target::All => {
+            batch::execute_for_all(options.adapter.as_ref(), options.retry_policy, sync);
+            return;
+        }
+        Target::Serial(serial) => serial,
+    };
 
     let mut thermostats = Thermostats::load().unwrap();
     let thermostat = thermostats.get(serial).expect("Unknown thermostat serial. You need to do a read first.");
-    let secret = &thermostat.secret;
-
-    let connected_peripheral = bluetooth::connect(|name| is_thermostat_name(name) && &stripped_name(name) == serial, false).unwrap();
+    if thermostat.needs_repairing {
+        eprintln!("The secret key of {} no longer matches the thermostat. Pair with it again using: eco2 repair {}", serial, serial);
+        std::process::exit(1);
+    }
 
-    update_characteristics(&thermostat, &secret, &connected_peripheral);
-    let thermostat_with_updated_values = read_from_connected_peripheral(&connected_peripheral, serial, Some(secret));
-    connected_peripheral.disconnect();
+    let result = bluetooth::connect(options.adapter.as_ref(), |name| is_thermostat_name(name) && &stripped_name(name) == serial, false, options.retry_policy)
+        .and_then(|connected_peripheral| {
+            let result = sync(&connected_peripheral, thermostat);
+            if let Err(error) = connected_peripheral.disconnect() {
+                warn!("Could not disconnect from {}: {}", serial, error);
+            }
+            result
+        });
 
+    let thermostat_with_updated_values = match result {
+        Ok(thermostat) => thermostat,
+        Err(error) => {
+            eprintln!("Could not sync {}: {}", serial, error);
+            std::process::exit(1);
+        }
+    };
     thermostats.push(thermostat_with_updated_values);
     thermostats.save().unwrap();
 }
 
-fn update_characteristics(thermostat: &Thermostat, secret: &Vec<u8>, connected_peripheral: &bluetooth::ConnectedBluetoothPeripheral) {
-    let alter_temperature = thermostat.new_set_point_temperature.is_some();
-    let alter_settings = thermostat.new_vacation_period.is_some() || thermostat.new_schedule_mode.is_some();
+/// Writes the pending properties of the thermostat and reads back all values.
+/// Properties that the thermostat did not confirm are kept as pending, so
+/// they will be written again on next sync.
+fn sync_connected_peripheral(connected_peripheral: &bluetooth::ConnectedBluetoothPeripheral, thermostat: &Thermostat, time_zone: TimeZoneSetting) -> btleplug::Result<Thermostat> {
+    if thermostat.needs_repairing {
+        return Err(btleplug::Error::Other(format!("The secret key no longer matches the thermostat. Run: eco2 repair {}", thermostat.serial)));
+    }
+    let mut thermostat = thermostat.clone();
+    program_next_vacation_period(&mut thermostat, time_zone);
+    let thermostat = &thermostat;
+
+    let property_writes = update_characteristics(thermostat, &thermostat.secret, connected_peripheral, time_zone)?;
+    let mut thermostat_with_updated_values = read_from_connected_peripheral(connected_peripheral, &thermostat.serial, Some(&thermostat.secret))?;
+    thermostat_with_updated_values.queued_vacation_periods = thermostat.queued_vacation_periods.clone();
 
-    // First, find out which characteristics we want to update
-    let mut characteristics_to_alter = HashSet::new();
-    if alter_temperature {
-        characteristics_to_alter.insert(bluetooth::TEMPERATURE.to_string());
+    for property_write in property_writes {
+        if property_write.confirmed {
+            eprintln!("{}: written", property_write.property);
+        } else {
+            eprintln!("{}: not confirmed by the thermostat. Will try again on next sync.", property_write.property);
+            property_write.property.keep_pending(thermostat, &mut thermostat_with_updated_values);
+        }
+    }
+
+    Ok(thermostat_with_updated_values)
+}
+
+// Uses the vacation period of the thermostat as it was last read, which is at
+// the latest when it was last synced
+fn program_next_vacation_period(thermostat: &mut Thermostat, time_zone: TimeZoneSetting) {
+    let current_vacation_end = match ParsedThermostat::try_from(&*thermostat) {
+        Ok(parsed_thermostat) => parsed_thermostat.vacation_period.map(|(_, end)| end.timestamp()),
+        Err(_) => return,
+    };
+    if let Some((start, end)) = thermostat.take_next_vacation_period(current_vacation_end, Utc::now().timestamp()) {
+        eprintln!("Programming the next queued vacation: {} - {}", time_zone.format(Utc.timestamp(start, 0)), time_zone.format(Utc.timestamp(end, 0)));
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Property {
+    SetPointTemperature,
+    VacationPeriod,
+    ScheduleMode,
+    DeviceSettings,
+    Time,
+}
+
+impl Property {
+    fn pending(thermostat: &Thermostat) -> Vec<Self> {
+        let mut result = vec![];
+        if thermostat.new_set_point_temperature.is_some() {
+            result.push(Self::SetPointTemperature);
+        }
+        if thermostat.new_vacation_period.is_some() {
+            result.push(Self::VacationPeriod);
+        }
+        if thermostat.new_schedule_mode.is_some() {
+            result.push(Self::ScheduleMode);
+        }
+        if !thermostat.new_device_settings.is_empty() {
+            result.push(Self::DeviceSettings);
+        }
+        if thermostat.new_time {
+            result.push(Self::Time);
+        }
+        result
+    }
+
+    fn characteristic(&self) -> &'static str {
+        match self {
+            Self::SetPointTemperature => bluetooth::TEMPERATURE,
+            Self::VacationPeriod | Self::ScheduleMode | Self::DeviceSettings => bluetooth::SETTINGS,
+            Self::Time => bluetooth::TIME,
+        }
+    }
+
+    // The decrypted bytes of the characteristic that hold this property
+    fn bytes(&self) -> Range<usize> {
+        match self {
+            Self::SetPointTemperature => 0..1,
+            Self::VacationPeriod => 6..14,
+            Self::ScheduleMode => 4..5,
+            // The temperature limits. The flags in byte 0 are never written.
+            Self::DeviceSettings => 1..3,
+            Self::Time => 0..8,
+        }
     }
-    if alter_settings {
-        characteristics_to_alter.insert(bluetooth::SETTINGS.to_string());
+
+    fn is_confirmed(&self, secret: &Vec<u8>, written_value: &Vec<u8>, read_value: &Vec<u8>) -> bool {
+        let written_value = decrypt(secret, written_value);
+        let read_value = decrypt(secret, read_value);
+        if *self == Self::Time {
+            return times_match(&written_value, &read_value);
+        }
+        match (written_value.get(self.bytes()), read_value.get(self.bytes())) {
+            (Some(written_bytes), Some(read_bytes)) => written_bytes == read_bytes,
+            _ => false,
+        }
+    }
+
+    fn keep_pending(&self, from: &Thermostat, to: &mut Thermostat) {
+        match self {
+            Self::SetPointTemperature => to.new_set_point_temperature = from.new_set_point_temperature,
+            Self::VacationPeriod => to.new_vacation_period = from.new_vacation_period,
+            Self::ScheduleMode => to.new_schedule_mode = from.new_schedule_mode,
+            Self::DeviceSettings => to.new_device_settings = from.new_device_settings.clone(),
+            Self::Time => to.new_time = from.new_time,
+        }
+    }
+}
+
+impl fmt::Display for Property {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::SetPointTemperature => write!(f, "Set-point temperature"),
+            Self::VacationPeriod => write!(f, "Vacation period"),
+            Self::ScheduleMode => write!(f, "Schedule mode"),
+            Self::DeviceSettings => write!(f, "Device settings"),
+            Self::Time => write!(f, "Time"),
+        }
     }
+}
+
+// The clock keeps running, so the time read back is a bit later than the one written
+fn times_match(written_value: &[u8], read_value: &[u8]) -> bool {
+    match (TimeBlock::decode(written_value), TimeBlock::decode(read_value)) {
+        (Ok(written), Ok(read)) => match (written.time, read.time) {
+            (Some(written_time), Some(read_time)) => written.utc_offset == read.utc_offset
+                && (read_time - written_time).num_seconds().abs() <= MAX_CONFIRMED_TIME_DIFFERENCE_SECONDS,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+struct PropertyWrite {
+    property: Property,
+    confirmed: bool,
+}
+
+fn update_characteristics(thermostat: &Thermostat, secret: &Vec<u8>, connected_peripheral: &bluetooth::ConnectedBluetoothPeripheral, time_zone: TimeZoneSetting) -> btleplug::Result<Vec<PropertyWrite>> {
+    // First, find out which characteristics we want to update
+    let mut properties = Property::pending(thermostat);
+    let characteristics_to_alter: HashSet<String> = properties.iter()
+        .map(|property| property.characteristic().to_string())
+        .collect();
 
     // Do nothing if we don't need to update any characteristics
-    if characteristics_to_alter.len() == 0 {
-        return;
+    if characteristics_to_alter.is_empty() {
+        return Ok(vec![]);
     }
 
     // Read the characteristics we want to update
-    let mut characteristic_values = connected_peripheral.read_characteristics(characteristics_to_alter).unwrap();
+    let mut characteristic_values = connected_peripheral.read_characteristics(characteristics_to_alter.clone())?;
+
+    // Never write values encrypted with a secret that doesn't match the thermostat
+    check_read_values(&characteristic_values, secret)
+        .map_err(|invalid_secret| btleplug::Error::Other(invalid_secret.to_string()))?;
+
+    // A thermostat without a time characteristic should still get its other properties
+    if thermostat.new_time && !characteristic_values.contains_key(bluetooth::TIME) {
+        eprintln!("{}: the thermostat has no time characteristic. Skipping it.", Property::Time);
+        properties.retain(|property| *property != Property::Time);
+    }
 
     // Update the characteristics we just read
     if let Some(set_point_temperature) = thermostat.new_set_point_temperature {
         let temperature = characteristic_values.get_mut(&bluetooth::TEMPERATURE.to_string()).unwrap();
-        *temperature = update_set_point_temperature(temperature, secret, set_point_temperature);
+        *temperature = update_set_point_temperature(temperature, secret, valid_temperature(set_point_temperature)?).map_err(decode_error)?;
     }
     if let Some((vacation_period_start, vacation_period_end)) = thermostat.new_vacation_period {
         let settings = characteristic_values.get_mut(&bluetooth::SETTINGS.to_string()).unwrap();
-        *settings = update_vacation_period(settings, secret, vacation_period_start, vacation_period_end);
+        *settings = update_vacation_period(settings, secret, vacation_period_start, vacation_period_end).map_err(decode_error)?;
     }
     if let Some(schedule_mode) = thermostat.new_schedule_mode {
         let settings = characteristic_values.get_mut(&bluetooth::SETTINGS.to_string()).unwrap();
-        *settings = update_schedule_mode(settings, secret, schedule_mode);
+        *settings = update_schedule_mode(settings, secret, schedule_mode).map_err(decode_error)?;
+    }
+    if !thermostat.new_device_settings.is_empty() {
+        let changes = &thermostat.new_device_settings;
+        let minimum_temperature = changes.minimum_temperature.map(valid_temperature).transpose()?;
+        let maximum_temperature = changes.maximum_temperature.map(valid_temperature).transpose()?;
+        let settings = characteristic_values.get_mut(bluetooth::SETTINGS).unwrap();
+        *settings = update_device_settings(settings, secret, minimum_temperature, maximum_temperature).map_err(decode_error)?;
+    }
+
+    if let Some(time) = characteristic_values.get_mut(bluetooth::TIME) {
+        *time = update_time(time, secret, time_zone.with_offset(Utc::now())).map_err(decode_error)?;
     }
 
-    // ...and finally write back the updated characteristics
+    // ...then write back the updated characteristics
     for (characteristic_name, characteristic_value) in characteristic_values.iter() {
-        connected_peripheral.write_data(&characteristic_name[..], characteristic_value).unwrap();
+        connected_peripheral.write_data(&characteristic_name[..], characteristic_value)?;
+    }
+
+    // ...and finally read them again to see whether the thermostat accepted the new values
+    let read_values = connected_peripheral.read_characteristics(characteristics_to_alter)?;
+    Ok(properties.into_iter().map(|property| {
+        let characteristic = property.characteristic().to_string();
+        let confirmed = match (characteristic_values.get(&characteristic), read_values.get(&characteristic)) {
+            (Some(written_value), Some(read_value)) => property.is_confirmed(secret, written_value, read_value),
+            _ => false,
+        };
+        PropertyWrite { property, confirmed }
+    }).collect())
+}
+
+fn decode_error(error: DecodeError) -> btleplug::Error {
+    btleplug::Error::Other(error.to_string())
+}
+
+// Temperatures set by older versions were not checked before they were stored
+fn valid_temperature(degrees_celcius: f32) -> btleplug::Result<Temperature> {
+    Temperature::try_from(degrees_celcius).map_err(|invalid_temperature| btleplug::Error::Other(invalid_temperature.to_string()))
+}
+
+fn check_read_values(characteristic_values: &HashMap<String, Vec<u8>>, secret: &Vec<u8>) -> Result<(), InvalidSecret> {
+    if let Some(temperature) = characteristic_values.get(bluetooth::TEMPERATURE) {
+        check_temperature(secret, temperature)?;
+    }
+    if let Some(settings) = characteristic_values.get(bluetooth::SETTINGS) {
+        check_settings(secret, settings)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::encrypt;
+
+    const SECRET: [u8; 16] = [215, 91, 125, 126, 14, 118, 62, 143, 121, 48, 110, 175, 112, 218, 245, 65];
+    const SETTINGS: [u8; 16] = [180, 249, 230, 196, 18, 146, 189, 34, 145, 102, 24, 26, 151, 111, 192, 189];
+
+    #[test]
+    fn it_knows_which_properties_are_pending() {
+        let thermostat = Thermostat {
+            new_set_point_temperature: Some(21.5),
+            new_schedule_mode: Some(0),
+            ..Default::default()
+        };
+
+        assert_eq!(vec![Property::SetPointTemperature, Property::ScheduleMode], Property::pending(&thermostat));
+    }
+
+    #[test]
+    fn it_confirms_property_when_read_value_matches_written_value() {
+        let secret = SECRET.to_vec();
+        let written_settings = update_schedule_mode(&SETTINGS.to_vec(), &secret, 3).unwrap();
+
+        assert!(Property::ScheduleMode.is_confirmed(&secret, &written_settings, &written_settings));
+    }
+
+    #[test]
+    fn it_does_not_confirm_property_when_read_value_differs() {
+        let secret = SECRET.to_vec();
+        let written_settings = update_schedule_mode(&SETTINGS.to_vec(), &secret, 3).unwrap();
+
+        assert!(!Property::ScheduleMode.is_confirmed(&secret, &written_settings, &SETTINGS.to_vec()));
+    }
+
+    #[test]
+    fn it_only_compares_the_bytes_of_the_property() {
+        let secret = SECRET.to_vec();
+        let written_settings = update_schedule_mode(&SETTINGS.to_vec(), &secret, 3).unwrap();
+        let read_settings = update_vacation_period(&written_settings, &secret, 1620824400, 1625040000).unwrap();
+
+        assert!(Property::ScheduleMode.is_confirmed(&secret, &written_settings, &read_settings));
+        assert!(!Property::VacationPeriod.is_confirmed(&secret, &written_settings, &read_settings));
+    }
+
+    #[test]
+    fn it_knows_when_device_settings_are_pending() {
+        let mut thermostat = Thermostat::default();
+        assert!(Property::pending(&thermostat).is_empty());
+
+        thermostat.new_device_settings.minimum_temperature = Some(10.0);
+        assert_eq!(vec![Property::DeviceSettings], Property::pending(&thermostat));
+    }
+
+    #[test]
+    fn it_confirms_time_when_read_back_a_bit_later() {
+        let secret = SECRET.to_vec();
+        let written_time = TimeBlock { time: Some(Utc.ymd(2021, 5, 24).and_hms(11, 0, 0)), utc_offset: 7200 };
+        let read_time = TimeBlock { time: Some(Utc.ymd(2021, 5, 24).and_hms(11, 0, 5)), utc_offset: 7200 };
+        let stale_time = TimeBlock { time: Some(Utc.ymd(2021, 5, 24).and_hms(10, 45, 0)), utc_offset: 7200 };
+        let encrypted = |time: &TimeBlock| encrypt(&secret, &time.encode());
+
+        assert!(Property::Time.is_confirmed(&secret, &encrypted(&written_time), &encrypted(&read_time)));
+        assert!(!Property::Time.is_confirmed(&secret, &encrypted(&written_time), &encrypted(&stale_time)));
+    }
+
+    #[test]
+    fn it_keeps_unconfirmed_properties_pending() {
+        let thermostat = Thermostat {
+            new_set_point_temperature: Some(21.5),
+            new_vacation_period: Some((0, 0)),
+            ..Default::default()
+        };
+        let mut thermostat_with_updated_values = Thermostat::default();
+
+        Property::VacationPeriod.keep_pending(&thermostat, &mut thermostat_with_updated_values);
+
+        assert_eq!(None, thermostat_with_updated_values.new_set_point_temperature);
+        assert_eq!(Some((0, 0)), thermostat_with_updated_values.new_vacation_period);
+    }
+
+    #[test]
+    fn it_accepts_read_values_that_match_the_secret() {
+        let mut characteristic_values = HashMap::new();
+        characteristic_values.insert(bluetooth::SETTINGS.to_string(), SETTINGS.to_vec());
+
+        assert!(check_read_values(&characteristic_values, &SECRET.to_vec()).is_ok());
+    }
+
+    #[test]
+    fn it_rejects_read_values_that_do_not_match_the_secret() {
+        let mut characteristic_values = HashMap::new();
+        characteristic_values.insert(bluetooth::SETTINGS.to_string(), SETTINGS.to_vec());
+        let other_secret = vec![1u8; 16];
+
+        assert!(check_read_values(&characteristic_values, &other_secret).is_err());
     }
 }
@@ -0,0 +1,50 @@
+use crate::models::time_zone::TimeZoneSetting;
+
+const TIME_ZONE_OPTION: &str = "--tz";
+
+/// Takes `--tz` out of the arguments. Without it, times are in the time zone
+/// of this computer.
+pub fn take(arguments: Vec<String>) -> (TimeZoneSetting, Vec<String>) {
+    let mut time_zone = TimeZoneSetting::default();
+    let mut remaining_arguments = vec![];
+
+    let mut arguments = arguments.into_iter();
+    while let Some(argument) = arguments.next() {
+        if argument == TIME_ZONE_OPTION {
+            let name = arguments.next().expect("Expected a time zone after --tz, like Europe/Copenhagen");
+            time_zone = match TimeZoneSetting::parse(&name) {
+                Some(time_zone) => time_zone,
+                None => panic!("Unknown time zone: {}. Expected local, or a name like Europe/Copenhagen", name),
+            };
+        } else {
+            remaining_arguments.push(argument);
+        }
+    }
+
+    (time_zone, remaining_arguments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::arguments;
+
+    #[test]
+    fn it_takes_time_zone_out_of_the_arguments() {
+        let (time_zone, remaining_arguments) = take(arguments(&["0:04:2F:06:24:D1", "--tz", "Europe/Copenhagen"]));
+
+        assert_eq!(TimeZoneSetting::Named(chrono_tz::Europe::Copenhagen), time_zone);
+        assert_eq!(arguments(&["0:04:2F:06:24:D1"]), remaining_arguments);
+    }
+
+    #[test]
+    fn it_uses_the_local_time_zone_by_default() {
+        assert_eq!(TimeZoneSetting::Local, take(arguments(&["0:04:2F:06:24:D1"])).0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown time zone: Mars/Olympus. Expected local, or a name like Europe/Copenhagen")]
+    fn it_panics_on_unknown_time_zone() {
+        take(arguments(&["--tz", "Mars/Olympus"]));
+    }
+}
@@ -0,0 +1,63 @@
+use crate::models::config::Config;
+use crate::models::temperature_unit::TemperatureUnit;
+
+const UNIT_OPTION: &str = "--unit";
+const UNIT_VARIABLE: &str = "ECO2_TEMPERATURE_UNIT";
+
+/// Takes `--unit` out of the arguments. Without it, the unit comes from the
+/// ECO2_TEMPERATURE_UNIT environment variable, then from the config, and is
+/// Celsius if none of them has it.
+pub fn take(arguments: Vec<String>) -> (TemperatureUnit, Vec<String>) {
+    let mut unit = None;
+    let mut remaining_arguments = vec![];
+
+    let mut arguments = arguments.into_iter();
+    while let Some(argument) = arguments.next() {
+        if argument == UNIT_OPTION {
+            let value = arguments.next().expect("Expected celsius or fahrenheit after --unit");
+            unit = Some(parse(&value, UNIT_OPTION));
+        } else {
+            remaining_arguments.push(argument);
+        }
+    }
+
+    (unit.unwrap_or_else(unit_or_default), remaining_arguments)
+}
+
+fn unit_or_default() -> TemperatureUnit {
+    if let Ok(value) = std::env::var(UNIT_VARIABLE) {
+        return parse(&value, UNIT_VARIABLE);
+    }
+    let config = Config::load().expect("Could not read config");
+    match config.temperature_unit {
+        Some(value) => parse(&value, "temperature_unit in the config"),
+        None => TemperatureUnit::default(),
+    }
+}
+
+fn parse(value: &str, source: &str) -> TemperatureUnit {
+    match TemperatureUnit::parse(value) {
+        Some(unit) => unit,
+        None => panic!("Unknown temperature unit in {}: {}. Expected celsius or fahrenheit", source, value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::arguments;
+
+    #[test]
+    fn it_takes_unit_out_of_the_arguments() {
+        let (unit, remaining_arguments) = take(arguments(&["0:04:2F:06:24:D1", "--unit", "fahrenheit"]));
+
+        assert_eq!(TemperatureUnit::Fahrenheit, unit);
+        assert_eq!(arguments(&["0:04:2F:06:24:D1"]), remaining_arguments);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown temperature unit in --unit: kelvin. Expected celsius or fahrenheit")]
+    fn it_panics_on_unknown_unit() {
+        take(arguments(&["--unit", "kelvin"]));
+    }
+}
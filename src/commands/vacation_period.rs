@@ -0,0 +1,232 @@
+use chrono::prelude::*;
+use chrono::Duration;
+
+const EXPECTED_TIME: &str = "Expected e.g. now, today 18:00, tomorrow 08:00, +3d, or 2021-05-24 13:00";
+const EXPECTED_DURATION: &str = "Expected e.g. 'for 2 weeks', 'for 3 days', or +12h";
+
+/// Parses the arguments to `set vacation-period` into the start and end of
+/// the vacation. The arguments can be
+///
+/// - a start and an end time, where the end can also be `until <time>`, or a
+///   duration from the start, like `for 2 weeks` or `+3d`
+/// - `until <time>` or `for <duration>`, starting now
+///
+/// The times are not rounded to whole hours.
+pub fn parse<Tz: TimeZone>(arguments: &[String], now: &DateTime<Tz>) -> (DateTime<Tz>, DateTime<Tz>) {
+    let joined_arguments = arguments.join(" ");
+    let (start, end) = if joined_arguments.starts_with("until ") || joined_arguments.starts_with("for ") {
+        (now.clone(), parse_end(&joined_arguments, now))
+    } else if arguments.len() == 2 {
+        let start = parse_time(&arguments[0], now);
+        let end = parse_end(&arguments[1], &start);
+        (start, end)
+    } else {
+        panic!("Expected a start and end time, 'until <time>', or 'for <duration>' as vacation period, got {} arguments", arguments.len());
+    };
+    if end <= start {
+        panic!("The vacation period must end after it starts");
+    }
+    (start, end)
+}
+
+/// Rounds to the nearest whole hour, as the thermostat only supports those
+pub fn round_to_whole_hour<Tz: TimeZone>(time: &DateTime<Tz>) -> DateTime<Tz> {
+    let seconds_into_hour = (time.minute() * 60 + time.second()) as i64;
+    let whole_hour = time.clone() - Duration::seconds(seconds_into_hour) - Duration::nanoseconds(time.nanosecond() as i64);
+    if seconds_into_hour >= 30 * 60 {
+        whole_hour + Duration::hours(1)
+    } else {
+        whole_hour
+    }
+}
+
+// The end of a vacation, which can be relative to its start
+fn parse_end<Tz: TimeZone>(argument: &str, start: &DateTime<Tz>) -> DateTime<Tz> {
+    let argument = argument.trim();
+    if let Some(time) = argument.strip_prefix("until ") {
+        parse_time(time, start)
+    } else if argument.starts_with("for ") || argument.starts_with('+') {
+        add_duration(start, argument)
+    } else {
+        parse_time(argument, start)
+    }
+}
+
+// A point in time. Relative times are relative to `base`.
+fn parse_time<Tz: TimeZone>(argument: &str, base: &DateTime<Tz>) -> DateTime<Tz> {
+    let argument = argument.trim();
+    if argument == "now" {
+        return base.clone();
+    }
+    if argument.starts_with('+') {
+        return add_duration(base, argument);
+    }
+
+    let (day, time_of_day) = match argument.find(' ') {
+        Some(space) => (&argument[..space], Some(argument[space + 1..].trim())),
+        None => (argument, None),
+    };
+    let today = base.naive_local().date();
+    let date = match day {
+        "today" => today,
+        "tomorrow" => today.succ(),
+        _ => NaiveDate::parse_from_str(day, "%Y-%m-%d").unwrap_or_else(|_| panic!("Could not parse time: {}. {}", argument, EXPECTED_TIME)),
+    };
+    let time_of_day = match time_of_day {
+        Some(time_of_day) => NaiveTime::parse_from_str(time_of_day, "%H:%M").unwrap_or_else(|_| panic!("Could not parse time: {}. {}", argument, EXPECTED_TIME)),
+        None => NaiveTime::from_hms(0, 0, 0),
+    };
+
+    in_time_zone(&date.and_time(time_of_day), base, argument)
+}
+
+// In the repeated hour when the clocks are put back, the first occurrence is used
+fn in_time_zone<Tz: TimeZone>(time: &NaiveDateTime, base: &DateTime<Tz>, argument: &str) -> DateTime<Tz> {
+    match base.timezone().from_local_datetime(time).earliest() {
+        Some(time) => time,
+        None => panic!("{} does not exist in the time zone, as the clocks are put forward", argument),
+    }
+}
+
+// Hours are added as elapsed time, while days and weeks keep the time of day
+// across daylight saving time changes
+fn add_duration<Tz: TimeZone>(time: &DateTime<Tz>, argument: &str) -> DateTime<Tz> {
+    match parse_duration(argument) {
+        (duration, true) => in_time_zone(&(time.naive_local() + duration), time, argument),
+        (duration, false) => time.clone() + duration,
+    }
+}
+
+// Like +3d, +12h, +2w, or 'for 2 weeks'. Also tells whether it is in whole days.
+fn parse_duration(argument: &str) -> (Duration, bool) {
+    let (amount, unit) = if let Some(duration) = argument.strip_prefix('+') {
+        let unit_start = duration.find(|character: char| !character.is_ascii_digit()).unwrap_or(duration.len());
+        duration.split_at(unit_start)
+    } else if let Some(duration) = argument.strip_prefix("for ") {
+        let duration = duration.trim();
+        let space = duration.find(' ').unwrap_or_else(|| panic!("Could not parse duration: {}. {}", argument, EXPECTED_DURATION));
+        (&duration[..space], &duration[space + 1..])
+    } else {
+        panic!("Could not parse duration: {}. {}", argument, EXPECTED_DURATION);
+    };
+
+    let amount = amount.trim().parse::<i64>().unwrap_or_else(|_| panic!("Could not parse duration: {}. {}", argument, EXPECTED_DURATION));
+    match unit.trim() {
+        "h" | "hour" | "hours" => (Duration::hours(amount), false),
+        "d" | "day" | "days" => (Duration::days(amount), true),
+        "w" | "week" | "weeks" => (Duration::weeks(amount), true),
+        _ => panic!("Could not parse duration: {}. {}", argument, EXPECTED_DURATION),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<FixedOffset> {
+        FixedOffset::east(3600).ymd(2026, 12, 20).and_hms(13, 27, 0)
+    }
+
+    fn time(day: u32, hour: u32, minute: u32) -> DateTime<FixedOffset> {
+        FixedOffset::east(3600).ymd(2026, 12, day).and_hms(hour, minute, 0)
+    }
+
+    fn parsed(arguments: &[&str]) -> (DateTime<FixedOffset>, DateTime<FixedOffset>) {
+        let arguments: Vec<String> = arguments.iter().map(|argument| argument.to_string()).collect();
+        parse(&arguments, &now())
+    }
+
+    #[test]
+    fn it_can_parse_absolute_times() {
+        assert_eq!((time(21, 13, 0), time(28, 10, 0)), parsed(&["2026-12-21 13:00", "2026-12-28 10:00"]));
+        assert_eq!((time(21, 0, 0), time(28, 0, 0)), parsed(&["2026-12-21", "2026-12-28"]));
+    }
+
+    #[test]
+    fn it_can_parse_relative_times() {
+        assert_eq!((now(), time(21, 8, 0)), parsed(&["now", "tomorrow 08:00"]));
+        assert_eq!((time(20, 18, 0), time(23, 18, 0)), parsed(&["today 18:00", "+3d"]));
+        assert_eq!((time(23, 13, 27), time(24, 1, 27)), parsed(&["+3d", "+12h"]));
+    }
+
+    #[test]
+    fn it_can_parse_until_and_for() {
+        assert_eq!((now(), time(28, 0, 0)), parsed(&["until", "2026-12-28"]));
+        assert_eq!((now(), time(28, 0, 0)), parsed(&["until 2026-12-28"]));
+        assert_eq!((now(), now() + Duration::weeks(2)), parsed(&["for", "2", "weeks"]));
+        assert_eq!((time(21, 8, 0), time(24, 8, 0)), parsed(&["tomorrow 08:00", "for 3 days"]));
+        assert_eq!((time(21, 8, 0), time(22, 10, 0)), parsed(&["tomorrow 08:00", "until 2026-12-22 10:00"]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Could not parse time: 24/5 2021 13:07. Expected e.g. now, today 18:00, tomorrow 08:00, +3d, or 2021-05-24 13:00")]
+    fn it_panics_when_given_invalid_date_format() {
+        parsed(&["24/5 2021 13:07", "+3d"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Could not parse duration: for 2 fortnights")]
+    fn it_panics_on_unknown_duration_unit() {
+        parsed(&["for 2 fortnights"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "The vacation period must end after it starts")]
+    fn it_panics_when_vacation_ends_before_it_starts() {
+        parsed(&["2026-12-28", "2026-12-21"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "The vacation period must end after it starts")]
+    fn it_panics_when_vacation_ends_before_now() {
+        parsed(&["until 2026-12-01"]);
+    }
+
+    #[test]
+    fn it_rounds_to_nearest_whole_hour() {
+        assert_eq!(time(20, 13, 0), round_to_whole_hour(&time(20, 13, 27)));
+        assert_eq!(time(20, 14, 0), round_to_whole_hour(&time(20, 13, 30)));
+        assert_eq!(time(21, 0, 0), round_to_whole_hour(&time(20, 23, 45)));
+        assert_eq!(time(20, 13, 0), round_to_whole_hour(&time(20, 13, 0)));
+    }
+
+    // Daylight saving time starts 2026-03-29 02:00 and ends 2026-10-25 03:00 in Copenhagen
+
+    fn parsed_in_copenhagen(arguments: &[&str]) -> (DateTime<Utc>, DateTime<Utc>) {
+        let arguments: Vec<String> = arguments.iter().map(|argument| argument.to_string()).collect();
+        let now = Utc.ymd(2026, 10, 19).and_hms(10, 0, 0).with_timezone(&chrono_tz::Europe::Copenhagen);
+        let (start, end) = parse(&arguments, &now);
+        (start.with_timezone(&Utc), end.with_timezone(&Utc))
+    }
+
+    #[test]
+    fn it_uses_the_offset_in_effect_at_each_time() {
+        let (start, end) = parsed_in_copenhagen(&["2026-10-24 12:00", "2026-10-26 12:00"]);
+
+        assert_eq!(Utc.ymd(2026, 10, 24).and_hms(10, 0, 0), start);
+        assert_eq!(Utc.ymd(2026, 10, 26).and_hms(11, 0, 0), end);
+        assert_eq!(Duration::hours(49), end - start);
+    }
+
+    #[test]
+    fn it_keeps_the_time_of_day_when_adding_days_across_daylight_saving_time() {
+        let (start, end) = parsed_in_copenhagen(&["2026-10-24 12:00", "for 2 days"]);
+        assert_eq!(Utc.ymd(2026, 10, 26).and_hms(11, 0, 0), end);
+        assert_eq!(Duration::hours(49), end - start);
+
+        let (_, end) = parsed_in_copenhagen(&["2026-10-24 12:00", "+24h"]);
+        assert_eq!(Utc.ymd(2026, 10, 25).and_hms(10, 0, 0), end);
+    }
+
+    #[test]
+    fn it_uses_the_first_occurrence_of_the_repeated_hour() {
+        let (start, _) = parsed_in_copenhagen(&["2026-10-25 02:30", "+3d"]);
+        assert_eq!(Utc.ymd(2026, 10, 25).and_hms(0, 30, 0), start);
+    }
+
+    #[test]
+    #[should_panic(expected = "2026-03-29 02:30 does not exist in the time zone, as the clocks are put forward")]
+    fn it_panics_on_times_skipped_when_the_clocks_are_put_forward() {
+        parsed_in_copenhagen(&["2026-03-29 02:30", "+3d"]);
+    }
+}
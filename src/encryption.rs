@@ -18,6 +18,11 @@ pub fn encrypt(secret: &Vec<u8>, value: &Vec<u8>) -> Vec<u8> {
   switch_endianness(&to_u8_vec(&encrypted_value_as_u32s))
 }
 
+// XXTEA works on at least two 32-bit words
+pub fn can_decrypt(value: &[u8]) -> bool {
+    value.len() >= 8 && value.len() % 4 == 0
+}
+
 fn switch_endianness(bytes: &Vec<u8>) -> Vec<u8> {
     let mut output = Vec::new();
 
@@ -145,4 +150,11 @@ mod tests {
       let encrypted_name = encrypt(&secret, &decrypted_name);
       assert_eq!(encrypted_name, vec![177, 174, 159, 196, 58, 140, 76, 22, 18, 192, 117, 144, 240, 100, 45, 250])
     }
+
+    #[test]
+    fn it_only_decrypts_whole_words() {
+      assert!(can_decrypt(&[0u8; 16]));
+      assert!(!can_decrypt(&[0u8; 1]));
+      assert!(!can_decrypt(&[0u8; 10]));
+    }
 }
\ No newline at end of file
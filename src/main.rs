@@ -6,7 +6,9 @@ mod commands;
 mod models;
 mod bluetooth;
 mod encryption;
-use commands::{forget, list, read, scan, set, show, sync};
+#[cfg(test)]
+mod test_helpers;
+use commands::{adapters, calendar, forget, inspect, list, raw, read, repair, scan, set, show, sync};
 
 fn main() {
     env_logger::init();
@@ -19,13 +21,18 @@ fn main() {
         .expect("No command given. Try using help.");
     let command_arguments: Vec<String> = command_line_arguments.collect();
     match command.as_str() {
+        "adapters" => adapters::execute(command_arguments),
         "scan" => scan::execute(command_arguments),
         "read" => read::execute(command_arguments),
         "sync" => sync::execute(command_arguments),
+        "repair" => repair::execute(command_arguments),
         "forget" => forget::execute(command_arguments),
         "list" => list::execute(command_arguments),
         "show" => show::execute(command_arguments),
         "set" => set::execute(command_arguments),
+        "calendar" => calendar::execute(command_arguments),
+        "inspect" => inspect::execute(command_arguments),
+        "raw" => raw::execute(command_arguments),
         "help" => quit_with_usage(program.as_str(), 0),
         _ => {
             println!("Unknown command {}", command);
@@ -38,13 +45,38 @@ fn quit_with_usage(program: &str, exit_code: i32) {
     println!("Usage: {} command [arguments]", program);
     println!("");
     println!("Commands:");
-    println!("scan - scan nearby devices for 120 seconds (Ctrl-C to stop)");
-    println!("read name - connect to and read specific thermostat");
-    println!("sync name - connect to specific thermostat, write all values not yet written, and read all values");
+    println!("adapters - list the Bluetooth adapters of this computer");
+    println!("scan [--timeout seconds] [--count n] [--json] [--adapter adapter] - scan nearby devices for 120 seconds (Ctrl-C to stop)");
+    println!("read name|--all - connect to and read specific thermostat, or all known thermostats");
+    println!("sync name|--all - connect to specific thermostat (or all known thermostats), write all values not yet written, and read all values");
+    println!("repair name - pair with a thermostat again after it has been reset, discarding the old secret key");
     println!("forget name - forget about a specific thermostat");
     println!("list - show all of the previously read thermostats");
     println!("show name - output all previously read values from a thermostat");
     println!("set name attribute value - set the given attribute to the provided value");
+    println!("calendar import file name...|--all [--match keyword] - queue the vacations in an iCalendar file for the given thermostats");
+    println!("inspect name [--diff file] - show the stored values of a thermostat byte by byte, or how they differ from those in an earlier copy of the thermostats file");
+    println!("raw dump name - read all characteristics of a thermostat, and decrypt them if possible");
+    println!("raw write name uuid hex [--encrypt] --confirm - write data to a characteristic, encrypting it first if asked to");
+    println!("");
+    println!("Options for read, sync, repair and raw:");
+    println!("--adapter name|index|address - the Bluetooth adapter to use (default from ~/.eco2.json, or the first adapter)");
+    println!("--attempts n - number of attempts for each Bluetooth operation (default 3)");
+    println!("--backoff seconds - delay before the first retry, doubling for each retry (default 1)");
+    println!("--no-reconnect - do not reconnect when the connection to a thermostat is lost");
+    println!("");
+    println!("Options for show, list and set:");
+    println!("--unit celsius|fahrenheit - the unit of temperatures (default from ECO2_TEMPERATURE_UNIT or ~/.eco2.json, or celsius)");
+    println!("");
+    println!("Options for show, set, sync and calendar:");
+    println!("--tz zone - the time zone of times, like Europe/Copenhagen (default local, the time zone of this computer)");
+    println!("");
+    println!("Options for sync:");
+    println!("--set-time - also set the clock of the thermostat to the time of this computer");
+    println!("");
+    println!("Options for read and repair:");
+    println!("--no-prompt - wait for the timer button to be clicked instead of asking you to press enter");
+    println!("--pairing-timeout seconds - how long to wait for the timer button to be clicked (default 60)");
 
     std::process::exit(exit_code)
 }
@@ -0,0 +1,454 @@
+use std::convert::TryInto;
+use std::fmt;
+use chrono::prelude::*;
+
+use crate::models::parsed_thermostat::{DailySchedule, ParsedThermostat, Temperature};
+
+/// The decrypted value of a characteristic does not have the expected length.
+#[derive(Debug, PartialEq)]
+pub struct DecodeError {
+    pub block: &'static str,
+    pub expected_length: usize,
+    pub actual_length: usize,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Expected {} bytes of {}, got {}", self.expected_length, self.block, self.actual_length)
+    }
+}
+
+fn check_length<'a>(block: &'static str, bytes: &'a [u8], expected_length: usize) -> Result<&'a [u8], DecodeError> {
+    if bytes.len() == expected_length {
+        Ok(bytes)
+    } else {
+        Err(DecodeError { block, expected_length, actual_length: bytes.len() })
+    }
+}
+
+pub fn decode_battery_level(bytes: &[u8]) -> Result<u8, DecodeError> {
+    let bytes = check_length("battery level", bytes, 1)?;
+    Ok(bytes[0])
+}
+
+// Seconds since epoch in UTC, big-endian. Zero means not set. The thermostat
+// doesn't know about time zones here; see TimeZoneSetting.
+pub fn decode_timestamp(bytes: &[u8]) -> Option<DateTime<Utc>> {
+    let mut seconds_since_epoch: i64 = 0;
+    for byte in bytes {
+        seconds_since_epoch *= 256;
+        seconds_since_epoch += *byte as i64;
+    }
+
+    if seconds_since_epoch == 0 {
+        None
+    } else {
+        Some(Utc.timestamp(seconds_since_epoch, 0))
+    }
+}
+
+fn encode_timestamp(timestamp: Option<DateTime<Utc>>) -> [u8; 4] {
+    let seconds_since_epoch = timestamp.map(|t| t.timestamp() as u32).unwrap_or(0);
+    seconds_since_epoch.to_be_bytes()
+}
+
+// Zero-terminated UTF-8, like the names of the thermostats. Letters like æ, ø
+// and å take two bytes each.
+pub fn decode_text(bytes: &[u8]) -> String {
+    let length = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..length]).into_owned()
+}
+
+/// The decrypted name characteristic: a zero-terminated name
+#[derive(Clone, Debug, PartialEq)]
+pub struct NameBlock {
+    pub name: String,
+    // As read, so a name that is not valid UTF-8 is written back unchanged
+    name_bytes: Vec<u8>,
+    // The terminating zero and whatever follows it
+    rest: Vec<u8>,
+}
+
+impl NameBlock {
+    const LENGTH: usize = 16;
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let bytes = check_length("name", bytes, Self::LENGTH)?;
+        let name_length = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+        Ok(Self {
+            name: decode_text(bytes),
+            name_bytes: bytes[..name_length].to_vec(),
+            rest: bytes[name_length..].to_vec(),
+        })
+    }
+
+    // Nothing writes this block yet
+    #[allow(dead_code)]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.name_bytes.clone();
+        bytes.extend(&self.rest);
+        bytes
+    }
+}
+
+/// The decrypted temperature characteristic
+#[derive(Clone, Debug, PartialEq)]
+pub struct TemperatureBlock {
+    pub set_point_temperature: Temperature,
+    pub room_temperature: Temperature,
+    unknown: [u8; 6],
+}
+
+impl TemperatureBlock {
+    const LENGTH: usize = 8;
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let bytes = check_length("temperature", bytes, Self::LENGTH)?;
+        Ok(Self {
+            set_point_temperature: Temperature::from_byte(bytes[0]),
+            room_temperature: Temperature::from_byte(bytes[1]),
+            unknown: bytes[2..8].try_into().unwrap(),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![self.set_point_temperature.to_byte(), self.room_temperature.to_byte()];
+        bytes.extend(&self.unknown);
+        bytes
+    }
+}
+
+/// A bit in the first byte of the settings characteristic. The bit positions
+/// are the ones libetrv, the Python implementation of the protocol, uses.
+/// They have not been checked against a capture of a flag changing: every
+/// capture we have has the flags 0x53. So they are only shown, never written.
+/// Bits 1 and 5 are not known.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SettingsFlag {
+    AdaptiveLearning,
+    VerticalInstallation,
+    DisplayFlipped,
+    SlowRegulation,
+    ValveInstalled,
+    ChildLock,
+}
+
+impl SettingsFlag {
+    fn mask(self) -> u8 {
+        match self {
+            Self::AdaptiveLearning => 0x01,
+            Self::VerticalInstallation => 0x04,
+            Self::DisplayFlipped => 0x08,
+            Self::SlowRegulation => 0x10,
+            Self::ValveInstalled => 0x40,
+            Self::ChildLock => 0x80,
+        }
+    }
+}
+
+/// The decrypted settings characteristic
+#[derive(Clone, Debug, PartialEq)]
+pub struct SettingsBlock {
+    flags: u8,
+    // The range the set-point temperature can be adjusted within on the thermostat
+    pub minimum_temperature: Temperature,
+    pub maximum_temperature: Temperature,
+    pub frost_protection_temperature: Temperature,
+    pub schedule_mode: u8,
+    pub vacation_temperature: Temperature,
+    pub vacation_start: Option<DateTime<Utc>>,
+    pub vacation_end: Option<DateTime<Utc>>,
+    unknown_end: [u8; 2],
+}
+
+impl SettingsBlock {
+    const LENGTH: usize = 16;
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let bytes = check_length("settings", bytes, Self::LENGTH)?;
+        Ok(Self {
+            flags: bytes[0],
+            minimum_temperature: Temperature::from_byte(bytes[1]),
+            maximum_temperature: Temperature::from_byte(bytes[2]),
+            frost_protection_temperature: Temperature::from_byte(bytes[3]),
+            schedule_mode: bytes[4],
+            vacation_temperature: Temperature::from_byte(bytes[5]),
+            vacation_start: decode_timestamp(&bytes[6..10]),
+            vacation_end: decode_timestamp(&bytes[10..14]),
+            unknown_end: bytes[14..16].try_into().unwrap(),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![self.flags, self.minimum_temperature.to_byte(), self.maximum_temperature.to_byte()];
+        bytes.push(self.frost_protection_temperature.to_byte());
+        bytes.push(self.schedule_mode);
+        bytes.push(self.vacation_temperature.to_byte());
+        bytes.extend(&encode_timestamp(self.vacation_start));
+        bytes.extend(&encode_timestamp(self.vacation_end));
+        bytes.extend(&self.unknown_end);
+        bytes
+    }
+
+    pub fn flag(&self, flag: SettingsFlag) -> bool {
+        self.flags & flag.mask() != 0
+    }
+}
+
+/// The decrypted time characteristic: the current time, and the offset of the
+/// time zone from UTC in seconds. This is the layout other implementations of
+/// the protocol use.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeBlock {
+    pub time: Option<DateTime<Utc>>,
+    pub utc_offset: i32,
+}
+
+impl TimeBlock {
+    const LENGTH: usize = 8;
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let bytes = check_length("time", bytes, Self::LENGTH)?;
+        Ok(Self {
+            time: decode_timestamp(&bytes[0..4]),
+            utc_offset: i32::from_be_bytes(bytes[4..8].try_into().unwrap()),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = encode_timestamp(self.time).to_vec();
+        bytes.extend(&self.utc_offset.to_be_bytes());
+        bytes
+    }
+}
+
+/// The times of a day where the thermostat switches between away and home,
+/// in 30-minute increments from midnight
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScheduleDay {
+    pub switch_points: [u8; 6],
+}
+
+impl ScheduleDay {
+    fn decode(bytes: &[u8]) -> Self {
+        Self { switch_points: bytes.try_into().unwrap() }
+    }
+
+    pub fn daily_schedule(&self) -> DailySchedule {
+        ParsedThermostat::decode_daily_schedule(&self.switch_points)
+    }
+}
+
+/// The decrypted first schedule characteristic
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScheduleBlock1 {
+    pub home_temperature: Temperature,
+    pub away_temperature: Temperature,
+    pub monday: ScheduleDay,
+    pub tuesday: ScheduleDay,
+    pub wednesday: ScheduleDay,
+}
+
+impl ScheduleBlock1 {
+    const LENGTH: usize = 20;
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let bytes = check_length("schedule 1", bytes, Self::LENGTH)?;
+        Ok(Self {
+            home_temperature: Temperature::from_byte(bytes[0]),
+            away_temperature: Temperature::from_byte(bytes[1]),
+            monday: ScheduleDay::decode(&bytes[2..8]),
+            tuesday: ScheduleDay::decode(&bytes[8..14]),
+            wednesday: ScheduleDay::decode(&bytes[14..20]),
+        })
+    }
+
+    // Nothing writes this block yet
+    #[allow(dead_code)]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![self.home_temperature.to_byte(), self.away_temperature.to_byte()];
+        bytes.extend(&self.monday.switch_points);
+        bytes.extend(&self.tuesday.switch_points);
+        bytes.extend(&self.wednesday.switch_points);
+        bytes
+    }
+}
+
+/// The decrypted second schedule characteristic
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScheduleBlock2 {
+    pub thursday: ScheduleDay,
+    pub friday: ScheduleDay,
+}
+
+impl ScheduleBlock2 {
+    const LENGTH: usize = 12;
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let bytes = check_length("schedule 2", bytes, Self::LENGTH)?;
+        Ok(Self {
+            thursday: ScheduleDay::decode(&bytes[0..6]),
+            friday: ScheduleDay::decode(&bytes[6..12]),
+        })
+    }
+
+    // Nothing writes this block yet
+    #[allow(dead_code)]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.thursday.switch_points.to_vec();
+        bytes.extend(&self.friday.switch_points);
+        bytes
+    }
+}
+
+/// The decrypted third schedule characteristic
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScheduleBlock3 {
+    pub saturday: ScheduleDay,
+    pub sunday: ScheduleDay,
+}
+
+impl ScheduleBlock3 {
+    const LENGTH: usize = 12;
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let bytes = check_length("schedule 3", bytes, Self::LENGTH)?;
+        Ok(Self {
+            saturday: ScheduleDay::decode(&bytes[0..6]),
+            sunday: ScheduleDay::decode(&bytes[6..12]),
+        })
+    }
+
+    // Nothing writes this block yet
+    #[allow(dead_code)]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.saturday.switch_points.to_vec();
+        bytes.extend(&self.sunday.switch_points);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::decrypt;
+
+    const SECRET: [u8; 16] = [215, 91, 125, 126, 14, 118, 62, 143, 121, 48, 110, 175, 112, 218, 245, 65];
+
+    fn decrypted(value: &[u8]) -> Vec<u8> {
+        decrypt(&SECRET.to_vec(), &value.to_vec())
+    }
+
+    #[test]
+    fn it_round_trips_name() {
+        let bytes = decrypted(&[177, 174, 159, 196, 58, 140, 76, 22, 18, 192, 117, 144, 240, 100, 45, 250]);
+        let block = NameBlock::decode(&bytes).unwrap();
+
+        assert_eq!("Alrum opgang", block.name);
+        assert_eq!(bytes, block.encode());
+    }
+
+    #[test]
+    fn it_decodes_name_as_utf8() {
+        let mut bytes = "Køkken".as_bytes().to_vec();
+        bytes.resize(16, 0);
+        let block = NameBlock::decode(&bytes).unwrap();
+
+        assert_eq!("Køkken", block.name);
+        assert_eq!(bytes, block.encode());
+    }
+
+    #[test]
+    fn it_writes_back_name_that_is_not_utf8_unchanged() {
+        let mut bytes = vec![b'K', 0xf8, b'k'];
+        bytes.resize(16, 0);
+        let block = NameBlock::decode(&bytes).unwrap();
+
+        assert_eq!("K\u{fffd}k", block.name);
+        assert_eq!(bytes, block.encode());
+    }
+
+    #[test]
+    fn it_round_trips_temperature() {
+        let bytes = decrypted(&[206, 158, 231, 129, 243, 102, 119, 22]);
+
+        assert_eq!(bytes, TemperatureBlock::decode(&bytes).unwrap().encode());
+    }
+
+    #[test]
+    fn it_round_trips_settings() {
+        for settings in [
+            [180u8, 249, 230, 196, 18, 146, 189, 34, 145, 102, 24, 26, 151, 111, 192, 189],
+            [38u8, 253, 23, 96, 139, 92, 198, 149, 168, 5, 146, 197, 239, 37, 35, 118],
+        ].iter() {
+            let bytes = decrypted(settings);
+
+            assert_eq!(bytes, SettingsBlock::decode(&bytes).unwrap().encode());
+        }
+    }
+
+    #[test]
+    fn it_decodes_flags_and_temperature_limits() {
+        let block = SettingsBlock::decode(&decrypted(&[180, 249, 230, 196, 18, 146, 189, 34, 145, 102, 24, 26, 151, 111, 192, 189])).unwrap();
+
+        assert!(block.flag(SettingsFlag::AdaptiveLearning));
+        assert!(!block.flag(SettingsFlag::VerticalInstallation));
+        assert!(!block.flag(SettingsFlag::DisplayFlipped));
+        assert!(block.flag(SettingsFlag::SlowRegulation));
+        assert!(block.flag(SettingsFlag::ValveInstalled));
+        assert!(!block.flag(SettingsFlag::ChildLock));
+        assert_eq!("6°C", block.minimum_temperature.to_string());
+        assert_eq!("28°C", block.maximum_temperature.to_string());
+    }
+
+    #[test]
+    fn it_round_trips_time() {
+        let block = TimeBlock {
+            time: Some(Utc.ymd(2021, 5, 24).and_hms(11, 0, 0)),
+            utc_offset: -3600,
+        };
+        let bytes = block.encode();
+
+        assert_eq!(vec![0x60, 0xab, 0x87, 0x30, 0xff, 0xff, 0xf1, 0xf0], bytes);
+        assert_eq!(block, TimeBlock::decode(&bytes).unwrap());
+    }
+
+    #[test]
+    fn it_decodes_vacation_period() {
+        let bytes = decrypted(&[38, 253, 23, 96, 139, 92, 198, 149, 168, 5, 146, 197, 239, 37, 35, 118]);
+        let block = SettingsBlock::decode(&bytes).unwrap();
+
+        assert_eq!(Some(Utc.ymd(2021, 4, 14).and_hms(8, 0, 0)), block.vacation_start);
+        assert_eq!(Some(Utc.ymd(2021, 5, 14).and_hms(12, 0, 0)), block.vacation_end);
+    }
+
+    #[test]
+    fn it_round_trips_schedules() {
+        let schedule_1 = decrypted(&[177, 191, 223, 32, 127, 196, 137, 136, 213, 11, 205, 247, 71, 30, 49, 92, 247, 241, 236, 206]);
+        let schedule_2 = decrypted(&[220, 194, 171, 34, 228, 17, 4, 228, 108, 49, 152, 155]);
+        let schedule_3 = decrypted(&[98, 242, 118, 159, 179, 69, 44, 123, 193, 42, 33, 37]);
+
+        assert_eq!(schedule_1, ScheduleBlock1::decode(&schedule_1).unwrap().encode());
+        assert_eq!(schedule_2, ScheduleBlock2::decode(&schedule_2).unwrap().encode());
+        assert_eq!(schedule_3, ScheduleBlock3::decode(&schedule_3).unwrap().encode());
+    }
+
+    #[test]
+    fn it_round_trips_arbitrary_bytes() {
+        let bytes: Vec<u8> = (0..20).map(|i| i * 13).collect();
+
+        assert_eq!(bytes[..8].to_vec(), TemperatureBlock::decode(&bytes[..8]).unwrap().encode());
+        assert_eq!(bytes[..16].to_vec(), SettingsBlock::decode(&bytes[..16]).unwrap().encode());
+        assert_eq!(bytes, ScheduleBlock1::decode(&bytes).unwrap().encode());
+    }
+
+    #[test]
+    fn it_fails_on_unexpected_length() {
+        assert_eq!(Err(DecodeError {
+            block: "settings",
+            expected_length: 16,
+            actual_length: 4,
+        }), SettingsBlock::decode(&[0u8; 4]));
+        assert!(NameBlock::decode(&[]).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use std::ops::Range;
+
+use crate::encryption::{can_decrypt, decrypt};
+use crate::models::blocks::{decode_text, decode_timestamp};
+use crate::models::parsed_thermostat::{ParsedThermostat, Temperature};
+use crate::models::thermostats::Thermostat;
+
+/// How the bytes of a field are decoded
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FieldKind {
+    Text,
+    Flags,
+    Percentage,
+    Temperature,
+    ScheduleMode,
+    Timestamp,
+    UtcOffset,
+    DailySchedule,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub name: &'static str,
+    pub bytes: Range<usize>,
+    pub kind: FieldKind,
+}
+
+impl Field {
+    fn new(name: &'static str, bytes: Range<usize>, kind: FieldKind) -> Self {
+        Self { name, bytes, kind }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self.kind {
+            FieldKind::Text => decode_text(bytes),
+            FieldKind::Flags => format!("{:08b}", bytes[0]),
+            FieldKind::Percentage => format!("{}%", bytes[0]),
+            FieldKind::Temperature => Temperature::from_byte(bytes[0]).to_string(),
+            FieldKind::ScheduleMode => ParsedThermostat::parse_schedule_mode(bytes[0]).to_string(),
+            FieldKind::Timestamp => match decode_timestamp(bytes) {
+                Some(timestamp) => timestamp.to_rfc3339(),
+                None => "not set".to_string(),
+            },
+            FieldKind::UtcOffset => {
+                let seconds = i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                format!("{}{:02}:{:02}", if seconds < 0 { "-" } else { "+" }, seconds.abs() / 3600, seconds.abs() % 3600 / 60)
+            }
+            FieldKind::DailySchedule => ParsedThermostat::decode_daily_schedule(bytes).to_string(),
+        }
+    }
+}
+
+/// What we know about the bytes of a characteristic, as stored on a thermostat.
+pub struct CharacteristicLayout {
+    pub name: &'static str,
+    pub encrypted: bool,
+    pub fields: Vec<Field>,
+    value: fn(&Thermostat) -> &Vec<u8>,
+}
+
+impl CharacteristicLayout {
+    /// The stored value, decrypted if the characteristic is encrypted
+    pub fn decrypted_value(&self, thermostat: &Thermostat) -> Vec<u8> {
+        let value = (self.value)(thermostat);
+        if self.encrypted && can_decrypt(value) {
+            decrypt(&thermostat.secret, value)
+        } else {
+            value.clone()
+        }
+    }
+
+    /// Splits `value` into the known fields, and a row for each unknown byte
+    pub fn rows(&self, value: &[u8]) -> Vec<Row> {
+        let mut rows = vec![];
+        let mut offset = 0;
+        while offset < value.len() {
+            match self.fields.iter().find(|field| field.bytes.start == offset && field.bytes.end <= value.len()) {
+                Some(field) => {
+                    rows.push(Row { bytes: field.bytes.clone(), field: Some(field.clone()) });
+                    offset = field.bytes.end;
+                }
+                None => {
+                    rows.push(Row { bytes: offset..offset + 1, field: None });
+                    offset += 1;
+                }
+            }
+        }
+        rows
+    }
+}
+
+/// A known field, or an unknown byte, of a characteristic
+#[derive(Debug, PartialEq)]
+pub struct Row {
+    pub bytes: Range<usize>,
+    pub field: Option<Field>,
+}
+
+pub fn layouts() -> Vec<CharacteristicLayout> {
+    use FieldKind::*;
+    vec![
+        CharacteristicLayout {
+            name: "name",
+            encrypted: true,
+            fields: vec![Field::new("name", 0..16, Text)],
+            value: |thermostat| &thermostat.name,
+        },
+        CharacteristicLayout {
+            name: "battery level",
+            encrypted: false,
+            fields: vec![Field::new("battery level", 0..1, Percentage)],
+            value: |thermostat| &thermostat.battery_level,
+        },
+        CharacteristicLayout {
+            name: "temperature",
+            encrypted: true,
+            fields: vec![
+                Field::new("set-point temperature", 0..1, Temperature),
+                Field::new("room temperature", 1..2, Temperature),
+            ],
+            value: |thermostat| &thermostat.temperature,
+        },
+        CharacteristicLayout {
+            name: "settings",
+            encrypted: true,
+            fields: vec![
+                Field::new("flags", 0..1, Flags),
+                Field::new("minimum temperature", 1..2, Temperature),
+                Field::new("maximum temperature", 2..3, Temperature),
+                Field::new("frost protection temperature", 3..4, Temperature),
+                Field::new("schedule mode", 4..5, ScheduleMode),
+                Field::new("vacation temperature", 5..6, Temperature),
+                Field::new("vacation start", 6..10, Timestamp),
+                Field::new("vacation end", 10..14, Timestamp),
+            ],
+            value: |thermostat| &thermostat.settings,
+        },
+        CharacteristicLayout {
+            name: "time",
+            encrypted: true,
+            fields: vec![
+                Field::new("time", 0..4, Timestamp),
+                Field::new("UTC offset", 4..8, UtcOffset),
+            ],
+            value: |thermostat| &thermostat.time,
+        },
+        CharacteristicLayout {
+            name: "schedule 1",
+            encrypted: true,
+            fields: vec![
+                Field::new("home temperature", 0..1, Temperature),
+                Field::new("away temperature", 1..2, Temperature),
+                Field::new("monday", 2..8, DailySchedule),
+                Field::new("tuesday", 8..14, DailySchedule),
+                Field::new("wednesday", 14..20, DailySchedule),
+            ],
+            value: |thermostat| &thermostat.schedule_1,
+        },
+        CharacteristicLayout {
+            name: "schedule 2",
+            encrypted: true,
+            fields: vec![
+                Field::new("thursday", 0..6, DailySchedule),
+                Field::new("friday", 6..12, DailySchedule),
+            ],
+            value: |thermostat| &thermostat.schedule_2,
+        },
+        CharacteristicLayout {
+            name: "schedule 3",
+            encrypted: true,
+            fields: vec![
+                Field::new("saturday", 0..6, DailySchedule),
+                Field::new("sunday", 6..12, DailySchedule),
+            ],
+            value: |thermostat| &thermostat.schedule_3,
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::create_scheduled_thermostat;
+
+    fn layout(name: &str) -> CharacteristicLayout {
+        layouts().into_iter().find(|layout| layout.name == name).unwrap()
+    }
+
+    #[test]
+    fn it_decrypts_encrypted_characteristics() {
+        let thermostat = create_scheduled_thermostat();
+
+        assert_eq!(b"Alrum opgang\0\0\0\0".to_vec(), layout("name").decrypted_value(&thermostat));
+        assert_eq!(vec![78u8], layout("battery level").decrypted_value(&thermostat));
+    }
+
+    #[test]
+    fn it_marks_bytes_outside_known_fields_as_unknown() {
+        let settings = layout("settings");
+        let rows = settings.rows(&[0u8; 16]);
+
+        let unknown_offsets: Vec<usize> = rows.iter().filter(|row| row.field.is_none()).map(|row| row.bytes.start).collect();
+        assert_eq!(vec![14, 15], unknown_offsets);
+        assert_eq!(Some("schedule mode"), rows[4].field.as_ref().map(|field| field.name));
+        assert_eq!(6..10, rows[6].bytes);
+    }
+
+    #[test]
+    fn it_does_not_use_fields_beyond_the_end_of_the_value() {
+        let rows = layout("temperature").rows(&[38u8]);
+
+        assert_eq!(1, rows.len());
+    }
+
+    #[test]
+    fn it_decodes_field_values() {
+        let thermostat = create_scheduled_thermostat();
+        let decrypted_settings = layout("settings").decrypted_value(&thermostat);
+
+        assert_eq!("Scheduled", Field::new("schedule mode", 4..5, FieldKind::ScheduleMode).decode(&decrypted_settings[4..5]));
+        assert_eq!("Unknown (2)", Field::new("schedule mode", 4..5, FieldKind::ScheduleMode).decode(&[2]));
+        assert_eq!("01010011", Field::new("flags", 0..1, FieldKind::Flags).decode(&decrypted_settings[0..1]));
+        assert_eq!("19.5°C", Field::new("temperature", 0..1, FieldKind::Temperature).decode(&[39]));
+        assert_eq!("-01:30", Field::new("UTC offset", 4..8, FieldKind::UtcOffset).decode(&(-5400i32).to_be_bytes()));
+        assert_eq!("not set", Field::new("vacation start", 6..10, FieldKind::Timestamp).decode(&[0, 0, 0, 0]));
+        assert_eq!("Køkken", Field::new("name", 0..16, FieldKind::Text).decode("Køkken\0\0\0\0\0\0\0\0\0".as_bytes()));
+    }
+}
@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Defaults for command line options, read from ~/.eco2.json.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Config {
+    /// The Bluetooth adapter to use when --adapter isn't given
+    #[serde(default)]
+    pub adapter: Option<String>,
+    /// celsius or fahrenheit, when neither --unit nor ECO2_TEMPERATURE_UNIT is given
+    #[serde(default)]
+    pub temperature_unit: Option<String>,
+    /// The category or word in the summary of calendar events that are
+    /// vacations, when --match isn't given
+    #[serde(default)]
+    pub calendar_keyword: Option<String>,
+}
+
+impl Config {
+    pub fn load() -> std::io::Result<Self> {
+        let file_path = Self::file_path()?;
+        if Path::new(&file_path[..]).exists() {
+            let serialized_config = fs::read_to_string(&file_path[..])?;
+            Ok(serde_json::from_str(&serialized_config[..])?)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    #[cfg(test)]
+    fn file_path() -> Result<String, std::io::Error> {
+        Ok("./.test-eco2.json".to_string())
+    }
+
+    #[cfg(not(test))]
+    fn file_path() -> Result<String, std::io::Error> {
+        match std::env::home_dir() {
+            Some(path) => Ok(format!(
+                "{}/.eco2.json",
+                path.into_os_string().into_string().unwrap()
+            )),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "Could not find home directory",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_can_deserialize_config() -> std::io::Result<()> {
+        let config: Config = serde_json::from_str(r#"{"adapter":"hci1","temperature_unit":"fahrenheit","calendar_keyword":"trip"}"#)?;
+
+        assert_eq!(Some("hci1".to_string()), config.adapter);
+        assert_eq!(Some("fahrenheit".to_string()), config.temperature_unit);
+        assert_eq!(Some("trip".to_string()), config.calendar_keyword);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_uses_defaults_for_missing_settings() -> std::io::Result<()> {
+        let config: Config = serde_json::from_str("{}")?;
+
+        assert_eq!(Config::default(), config);
+
+        Ok(())
+    }
+}
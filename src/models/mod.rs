@@ -1,3 +1,10 @@
 pub mod thermostats;
 pub mod parsed_thermostat;
 pub mod thermostat_names;
+pub mod secret_check;
+pub mod config;
+pub mod characteristic_layout;
+pub mod blocks;
+pub mod temperature_unit;
+pub mod time_zone;
+pub mod vacation_calendar;
@@ -1,38 +1,97 @@
+use std::convert::TryFrom;
 use std::fmt;
 use chrono::prelude::*;
 
+use crate::models::blocks::{decode_battery_level, DecodeError, NameBlock, ScheduleBlock1, ScheduleBlock2, ScheduleBlock3, SettingsBlock, SettingsFlag, TemperatureBlock, TimeBlock};
 use crate::models::thermostats::Thermostat;
-use crate::encryption::{decrypt, encrypt};
+use crate::encryption::{can_decrypt, decrypt, encrypt};
 
-pub fn update_set_point_temperature(encrypted_temperature: &Vec<u8>, secret: &Vec<u8>, set_point_temperature: f32) -> Vec<u8> {
-    let temperature = Temperature::from_degrees_celcius(set_point_temperature);
+pub fn update_set_point_temperature(encrypted_temperature: &Vec<u8>, secret: &Vec<u8>, set_point_temperature: Temperature) -> Result<Vec<u8>, DecodeError> {
+    let mut temperature = decrypt_block(secret, encrypted_temperature, TemperatureBlock::decode)?;
+    temperature.set_point_temperature = set_point_temperature;
 
-    let mut decrypted_temperature = decrypt(secret, encrypted_temperature);
-    decrypted_temperature[0] = temperature.value;
+    Ok(encrypt(secret, &temperature.encode()))
+}
+
+pub fn update_vacation_period(encrypted_settings: &Vec<u8>, secret: &Vec<u8>, vacation_start: i64, vacation_end: i64) -> Result<Vec<u8>, DecodeError> {
+    let mut settings = decrypt_block(secret, encrypted_settings, SettingsBlock::decode)?;
+    settings.vacation_start = timestamp(vacation_start);
+    settings.vacation_end = timestamp(vacation_end);
+
+    Ok(encrypt(secret, &settings.encode()))
+}
+
+pub fn update_schedule_mode(encrypted_settings: &Vec<u8>, secret: &Vec<u8>, vacation_mode: u8) -> Result<Vec<u8>, DecodeError> {
+    let mut settings = decrypt_block(secret, encrypted_settings, SettingsBlock::decode)?;
+    settings.schedule_mode = vacation_mode;
 
-    encrypt(secret, &decrypted_temperature)
+    Ok(encrypt(secret, &settings.encode()))
 }
 
-pub fn update_vacation_period(encrypted_settings: &Vec<u8>, secret: &Vec<u8>, vacation_start: i64, vacation_end: i64) -> Vec<u8> {
-    let mut decrypted_settings = decrypt(secret, encrypted_settings);
-    replace_four_bytes(vacation_start, &mut decrypted_settings, 6);
-    replace_four_bytes(vacation_end, &mut decrypted_settings, 10);
+/// `None` leaves a temperature limit as it is
+pub fn update_device_settings(encrypted_settings: &Vec<u8>, secret: &Vec<u8>, minimum_temperature: Option<Temperature>, maximum_temperature: Option<Temperature>) -> Result<Vec<u8>, DecodeError> {
+    let mut settings = decrypt_block(secret, encrypted_settings, SettingsBlock::decode)?;
+    if let Some(minimum_temperature) = minimum_temperature {
+        settings.minimum_temperature = minimum_temperature;
+    }
+    if let Some(maximum_temperature) = maximum_temperature {
+        settings.maximum_temperature = maximum_temperature;
+    }
 
-    encrypt(secret, &decrypted_settings)
+    Ok(encrypt(secret, &settings.encode()))
 }
 
-pub fn update_schedule_mode(encrypted_settings: &Vec<u8>, secret: &Vec<u8>, vacation_mode: u8) -> Vec<u8> {
-    let mut decrypted_settings = decrypt(secret, encrypted_settings);
-    decrypted_settings[4] = vacation_mode;
+/// Sets the clock of the thermostat, along with the UTC offset in effect at `time`
+pub fn update_time(encrypted_time: &Vec<u8>, secret: &Vec<u8>, time: DateTime<FixedOffset>) -> Result<Vec<u8>, DecodeError> {
+    let mut time_block = decrypt_block(secret, encrypted_time, TimeBlock::decode)?;
+    time_block.time = Some(time.with_timezone(&Utc));
+    time_block.utc_offset = time.offset().local_minus_utc();
 
-    encrypt(secret, &decrypted_settings)
+    Ok(encrypt(secret, &time_block.encode()))
+}
+
+// Zero resets the timestamp
+fn timestamp(seconds_since_epoch: i64) -> Option<DateTime<Utc>> {
+    if seconds_since_epoch == 0 {
+        None
+    } else {
+        Some(Utc.timestamp(seconds_since_epoch, 0))
+    }
+}
+
+// Values that cannot be decrypted don't have the length of any block, so
+// decoding them as they are gives the right error
+fn decrypt_block<T>(secret: &Vec<u8>, encrypted_value: &Vec<u8>, decode: fn(&[u8]) -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+    if can_decrypt(encrypted_value) {
+        decode(&decrypt(secret, encrypted_value))
+    } else {
+        decode(encrypted_value)
+    }
+}
+
+/// The stored data of a thermostat cannot be parsed, e.g. because the file
+/// has been corrupted, or the firmware uses a layout we don't know.
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub characteristic: &'static str,
+    pub offset: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cannot parse {} at offset {}: {}", self.characteristic, self.offset, self.reason)
+    }
 }
 
-fn replace_four_bytes(value: i64, existing_bytes: &mut Vec<u8>, start_index: usize) {
-    let mut counter = value;
-    for i in 0..4 {
-        existing_bytes[start_index + 3 - i] = (counter % 256) as u8;
-        counter /= 256;
+impl From<DecodeError> for ParseError {
+    fn from(error: DecodeError) -> Self {
+        Self {
+            characteristic: error.block,
+            // The first byte that is missing, or shouldn't be there
+            offset: error.actual_length.min(error.expected_length),
+            reason: error.to_string(),
+        }
     }
 }
 
@@ -45,6 +104,9 @@ pub struct ParsedThermostat {
     pub frost_protection_temperature: Temperature,
     pub schedule_mode: ScheduleMode,
     pub vacation_period: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    pub device_settings: DeviceSettings,
+    // None if the thermostat's clock hasn't been read, or isn't set
+    pub clock: Option<Clock>,
     pub schedule_monday: DailySchedule,
     pub schedule_tuesday: DailySchedule,
     pub schedule_wednesday: DailySchedule,
@@ -54,90 +116,63 @@ pub struct ParsedThermostat {
     pub schedule_sunday: DailySchedule,
 }
 
-impl ParsedThermostat {
-    pub fn from_thermostat(thermostat: &Thermostat) -> Self {
-        let decrypted_name = decrypt(&thermostat.secret, &thermostat.name);
-        let decrypted_temperature = decrypt(&thermostat.secret, &thermostat.temperature);
-        let decrypted_settings = decrypt(&thermostat.secret, &thermostat.settings);
-        let decrypted_schedule_1 = decrypt(&thermostat.secret, &thermostat.schedule_1);
-        let decrypted_schedule_2 = decrypt(&thermostat.secret, &thermostat.schedule_2);
-        let decrypted_schedule_3 = decrypt(&thermostat.secret, &thermostat.schedule_3);
-    
-        let battery_percentage = thermostat.battery_level[0];
-
-        let set_point_temperature = Temperature::from_byte(decrypted_temperature[0]);
-        let room_temperature = Temperature::from_byte(decrypted_temperature[1]);
-        let vacation_temperature = Temperature::from_byte(decrypted_settings[5]);
-        let frost_protection_temperature = Temperature::from_byte(decrypted_settings[3]);
-
-        let schedule_mode = Self::parse_schedule_mode(decrypted_settings[4]);
-
-        let start_vacation = Self::decode_datetime(&decrypted_settings[6..10]);
-        let end_vacation = Self::decode_datetime(&decrypted_settings[10..14]);
-        let vacation_period = match (start_vacation, end_vacation) {
+impl TryFrom<&Thermostat> for ParsedThermostat {
+    type Error = ParseError;
+
+    fn try_from(thermostat: &Thermostat) -> Result<Self, Self::Error> {
+        let name = decrypt_block(&thermostat.secret, &thermostat.name, NameBlock::decode)?;
+        let temperature = decrypt_block(&thermostat.secret, &thermostat.temperature, TemperatureBlock::decode)?;
+        let settings = decrypt_block(&thermostat.secret, &thermostat.settings, SettingsBlock::decode)?;
+        let schedule_1 = decrypt_block(&thermostat.secret, &thermostat.schedule_1, ScheduleBlock1::decode)?;
+        let schedule_2 = decrypt_block(&thermostat.secret, &thermostat.schedule_2, ScheduleBlock2::decode)?;
+        let schedule_3 = decrypt_block(&thermostat.secret, &thermostat.schedule_3, ScheduleBlock3::decode)?;
+
+        let battery_percentage = decode_battery_level(&thermostat.battery_level)?;
+
+        let clock = if thermostat.time.is_empty() {
+            None
+        } else {
+            let time = decrypt_block(&thermostat.secret, &thermostat.time, TimeBlock::decode)?;
+            time.time.map(|device_time| Clock {
+                time: device_time,
+                utc_offset: time.utc_offset,
+                drift: thermostat.time_read_at.map(|read_at| device_time.timestamp() - read_at),
+            })
+        };
+
+        let vacation_period = match (settings.vacation_start, settings.vacation_end) {
             (Some(start), Some(end)) => Some((start, end)),
             _ => None,
         };
 
-        let schedule_monday = Self::decode_daily_schedule(&decrypted_schedule_1[2..8]);
-        let schedule_tuesday = Self::decode_daily_schedule(&decrypted_schedule_1[8..14]);
-        let schedule_wednesday = Self::decode_daily_schedule(&decrypted_schedule_1[14..20]);
-        let schedule_thursday = Self::decode_daily_schedule(&decrypted_schedule_2[0..6]);
-        let schedule_friday = Self::decode_daily_schedule(&decrypted_schedule_2[6..12]);
-        let schedule_saturday = Self::decode_daily_schedule(&decrypted_schedule_3[0..6]);
-        let schedule_sunday = Self::decode_daily_schedule(&decrypted_schedule_3[6..12]);
-
-        Self {
-            name: Self::decode_name(&decrypted_name),
+        Ok(Self {
+            name: name.name,
             battery_percentage,
-            set_point_temperature,
-            room_temperature,
-            vacation_temperature,
-            frost_protection_temperature,
-            schedule_mode,
+            set_point_temperature: temperature.set_point_temperature,
+            room_temperature: temperature.room_temperature,
+            vacation_temperature: settings.vacation_temperature,
+            frost_protection_temperature: settings.frost_protection_temperature,
+            schedule_mode: Self::parse_schedule_mode(settings.schedule_mode),
             vacation_period,
-            schedule_monday,
-            schedule_tuesday,
-            schedule_wednesday,
-            schedule_thursday,
-            schedule_friday,
-            schedule_saturday,
-            schedule_sunday,
-        }
+            device_settings: DeviceSettings::from(&settings),
+            clock,
+            schedule_monday: schedule_1.monday.daily_schedule(),
+            schedule_tuesday: schedule_1.tuesday.daily_schedule(),
+            schedule_wednesday: schedule_1.wednesday.daily_schedule(),
+            schedule_thursday: schedule_2.thursday.daily_schedule(),
+            schedule_friday: schedule_2.friday.daily_schedule(),
+            schedule_saturday: schedule_3.saturday.daily_schedule(),
+            schedule_sunday: schedule_3.sunday.daily_schedule(),
+        })
     }
+}
 
+impl ParsedThermostat {
     pub fn parse_schedule_mode(schedule_mode: u8) -> ScheduleMode {
-        match schedule_mode {
-            0 => ScheduleMode::Manual,
-            1 => ScheduleMode::Scheduled,
-            3 => ScheduleMode::Vacation,
-            _ => panic!("Unknown schedule mode: {}", schedule_mode),
-        }
-    }
-
-    fn decode_name(encoded_name: &Vec<u8>) -> String {
-        let mut result = String::new();
-        encoded_name.iter()
-            .filter(|i| **i != 0)
-            .for_each(|i| result.push((*i as u8) as char));
-        result
+        ScheduleMode::from_byte(schedule_mode)
     }
 
-    fn decode_datetime(bytes: &[u8]) -> Option<DateTime<Utc>> {
-        let mut seconds_since_epoch: i64 = 0;
-        for byte in bytes {
-            seconds_since_epoch *= 256;
-            seconds_since_epoch += *byte as i64;
-        }
-
-        if seconds_since_epoch == 0 {
-            None
-        } else {
-            Some(Utc.timestamp(seconds_since_epoch, 0))
-        }
-    }
-
-    fn decode_daily_schedule(bytes: &[u8]) -> DailySchedule {
+    pub fn decode_daily_schedule(bytes: &[u8]) -> DailySchedule {
         let mut away = true;
         let mut intervals = vec![];
         let mut last_byte = bytes[0];
@@ -177,18 +212,18 @@ impl ParsedThermostat {
     }
 }
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
 pub struct Temperature {
     value: u8,
 }
 
 impl Temperature {
-    fn from_byte(byte: u8) -> Self {
+    pub fn from_byte(byte: u8) -> Self {
         Self { value: byte.clone() }
     }
 
-    fn from_degrees_celcius(degrees_celcius: f32) -> Self {
-        Self { value: (degrees_celcius * 2.0) as u8 }
+    pub fn to_byte(self) -> u8 {
+        self.value
     }
 
     pub fn in_degrees_celcius(&self) -> f32 {
@@ -196,17 +231,126 @@ impl Temperature {
     }
 }
 
+impl Temperature {
+    // The range the thermostat can regulate within
+    pub const MINIMUM_DEGREES_CELCIUS: f32 = 5.0;
+    pub const MAXIMUM_DEGREES_CELCIUS: f32 = 28.0;
+}
+
+/// Rounds to the nearest half degree, as that is what the thermostat stores
+impl TryFrom<f32> for Temperature {
+    type Error = InvalidTemperature;
+
+    fn try_from(degrees_celcius: f32) -> Result<Self, Self::Error> {
+        if !(Self::MINIMUM_DEGREES_CELCIUS..=Self::MAXIMUM_DEGREES_CELCIUS).contains(&degrees_celcius) {
+            return Err(InvalidTemperature { degrees_celcius });
+        }
+        Ok(Self { value: (degrees_celcius * 2.0).round() as u8 })
+    }
+}
+
 impl fmt::Display for Temperature {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}°C", self.in_degrees_celcius())
     }
 }
 
-#[derive(Eq, PartialEq, Debug)]
+/// A temperature outside the range the thermostat can regulate within
+#[derive(Debug, PartialEq)]
+pub struct InvalidTemperature {
+    pub degrees_celcius: f32,
+}
+
+impl fmt::Display for InvalidTemperature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}°C is outside the range of the thermostat, {}°C to {}°C", self.degrees_celcius, Temperature::MINIMUM_DEGREES_CELCIUS, Temperature::MAXIMUM_DEGREES_CELCIUS)
+    }
+}
+
+/// The configuration flags and temperature limits of a thermostat, as set
+/// up when it is installed
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct DeviceSettings {
+    pub adaptive_learning: bool,
+    pub vertical_installation: bool,
+    pub display_flipped: bool,
+    pub slow_regulation: bool,
+    pub valve_installed: bool,
+    pub child_lock: bool,
+    pub minimum_temperature: Temperature,
+    pub maximum_temperature: Temperature,
+}
+
+impl From<&SettingsBlock> for DeviceSettings {
+    fn from(settings: &SettingsBlock) -> Self {
+        Self {
+            adaptive_learning: settings.flag(SettingsFlag::AdaptiveLearning),
+            vertical_installation: settings.flag(SettingsFlag::VerticalInstallation),
+            display_flipped: settings.flag(SettingsFlag::DisplayFlipped),
+            slow_regulation: settings.flag(SettingsFlag::SlowRegulation),
+            valve_installed: settings.flag(SettingsFlag::ValveInstalled),
+            child_lock: settings.flag(SettingsFlag::ChildLock),
+            minimum_temperature: settings.minimum_temperature,
+            maximum_temperature: settings.maximum_temperature,
+        }
+    }
+}
+
+/// The thermostat's clock, as it was when last read
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Clock {
+    pub time: DateTime<Utc>,
+    // In seconds
+    pub utc_offset: i32,
+    // How many seconds the clock was ahead of this computer's clock. None if
+    // we don't know when it was read.
+    pub drift: Option<i64>,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
 pub enum ScheduleMode {
     Manual,
     Scheduled,
-    Vacation
+    Vacation,
+    /// Any other mode byte. Other modes, like 2 or a hold/override mode, could
+    /// not be identified: no capture of a thermostat in such a mode exists, so
+    /// they are not named until one shows what they are.
+    Unknown(u8),
+}
+
+impl ScheduleMode {
+    /// Only small values have been seen. A larger mode byte means the settings
+    /// were decrypted with the wrong secret, so it must never be written.
+    pub const MAX_VALUE: u8 = 7;
+
+    pub fn from_byte(byte: u8) -> Self {
+        match byte {
+            0 => Self::Manual,
+            1 => Self::Scheduled,
+            3 => Self::Vacation,
+            _ => Self::Unknown(byte),
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Self::Manual => 0,
+            Self::Scheduled => 1,
+            Self::Vacation => 3,
+            Self::Unknown(byte) => byte,
+        }
+    }
+
+    /// Parses a schedule mode name, or the raw value of the mode byte up to
+    /// `MAX_VALUE`
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "manual" => Some(Self::Manual),
+            "scheduled" => Some(Self::Scheduled),
+            "vacation" => Some(Self::Vacation),
+            _ => name.parse::<u8>().ok().filter(|byte| *byte <= Self::MAX_VALUE).map(Self::from_byte),
+        }
+    }
 }
 
 impl fmt::Display for ScheduleMode {
@@ -215,6 +359,7 @@ impl fmt::Display for ScheduleMode {
             Self::Manual => write!(f, "Manual"),
             Self::Scheduled => write!(f, "Scheduled"),
             Self::Vacation => write!(f, "Vacation"),
+            Self::Unknown(byte) => write!(f, "Unknown ({})", byte),
         }
     }
 }
@@ -257,6 +402,9 @@ pub enum TimeSetting {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_helpers::create_thermostat;
+    use std::convert::TryInto;
+    use crate::models::blocks::decode_timestamp;
 
     #[test]
     fn it_can_update_set_point_temperature() {
@@ -264,7 +412,7 @@ mod tests {
         let encrypted_temperature = vec![87u8, 121, 70, 227, 189, 210, 0, 110];
         let old_decrypted_temperature = decrypt(&secret, &encrypted_temperature);
 
-        let updated_encrypted_temperature = update_set_point_temperature(&encrypted_temperature, &secret, 18.5);
+        let updated_encrypted_temperature = update_set_point_temperature(&encrypted_temperature, &secret, Temperature::try_from(18.5).unwrap()).unwrap();
         let decrypted_temperature = decrypt(&secret, &updated_encrypted_temperature);
 
         // The written value should be 18.5 * 2
@@ -281,18 +429,94 @@ mod tests {
         let vacation_start = Local.ymd(2021, 5, 24).and_hms(13, 0, 0).timestamp();
         let vacation_end = Local.ymd(2021, 6, 30).and_hms(10, 0, 0).timestamp();
 
-        let updated_encrypted_settings = update_vacation_period(&encrypted_settings, &secret, vacation_start, vacation_end);
+        let updated_encrypted_settings = update_vacation_period(&encrypted_settings, &secret, vacation_start, vacation_end).unwrap();
         let decrypted_settings = decrypt(&secret, &updated_encrypted_settings);
 
         let vacation_start_bytes = &decrypted_settings[6..10];
         let vacation_end_bytes = &decrypted_settings[10..14];
 
-        assert_eq!(vacation_start, ParsedThermostat::decode_datetime(vacation_start_bytes).unwrap().timestamp());
-        assert_eq!(vacation_end, ParsedThermostat::decode_datetime(vacation_end_bytes).unwrap().timestamp());
+        assert_eq!(vacation_start, decode_timestamp(vacation_start_bytes).unwrap().timestamp());
+        assert_eq!(vacation_end, decode_timestamp(vacation_end_bytes).unwrap().timestamp());
         assert_eq!(old_decrypted_settings[0..6], decrypted_settings[0..6]);
         assert_eq!(old_decrypted_settings[14..], decrypted_settings[14..]);
     }
 
+    #[test]
+    fn it_rounds_temperature_to_nearest_half_degree() {
+        assert_eq!(Temperature::from_byte(43), Temperature::try_from(21.3).unwrap());
+        assert_eq!(Temperature::from_byte(42), Temperature::try_from(21.2).unwrap());
+        assert_eq!(Temperature::from_byte(10), Temperature::try_from(5.0).unwrap());
+        assert_eq!(Temperature::from_byte(56), Temperature::try_from(28.0).unwrap());
+    }
+
+    #[test]
+    fn it_rejects_temperature_outside_range() {
+        assert_eq!(Err(InvalidTemperature { degrees_celcius: -5.0 }), Temperature::try_from(-5.0));
+        assert_eq!(Err(InvalidTemperature { degrees_celcius: 300.0 }), Temperature::try_from(300.0));
+        assert!(Temperature::try_from(28.1).is_err());
+        assert!(Temperature::try_from(f32::NAN).is_err());
+        assert_eq!("300°C is outside the range of the thermostat, 5°C to 28°C", InvalidTemperature { degrees_celcius: 300.0 }.to_string());
+    }
+
+    #[test]
+    fn it_can_update_device_settings() {
+        let secret = vec![215u8, 91, 125, 126, 14, 118, 62, 143, 121, 48, 110, 175, 112, 218, 245, 65];
+        let encrypted_settings = vec![180u8, 249, 230, 196, 18, 146, 189, 34, 145, 102, 24, 26, 151, 111, 192, 189];
+        let old_decrypted_settings = decrypt(&secret, &encrypted_settings);
+
+        let updated_encrypted_settings = update_device_settings(&encrypted_settings, &secret, None, Some(Temperature::try_from(24.5).unwrap())).unwrap();
+        let decrypted_settings = decrypt(&secret, &updated_encrypted_settings);
+
+        assert_eq!(old_decrypted_settings[0..2], decrypted_settings[0..2]);
+        assert_eq!(49, decrypted_settings[2]);
+        assert_eq!(old_decrypted_settings[3..], decrypted_settings[3..]);
+    }
+
+    #[test]
+    fn it_can_decrypt_and_decode_device_settings() {
+        let device_settings = create_parsed_thermostat_with_schedule().device_settings;
+
+        assert!(device_settings.adaptive_learning);
+        assert!(device_settings.slow_regulation);
+        assert!(device_settings.valve_installed);
+        assert!(!device_settings.vertical_installation);
+        assert!(!device_settings.child_lock);
+        assert_eq!(6.0, device_settings.minimum_temperature.in_degrees_celcius());
+        assert_eq!(28.0, device_settings.maximum_temperature.in_degrees_celcius());
+    }
+
+    #[test]
+    fn it_can_update_time() {
+        let secret = vec![215u8, 91, 125, 126, 14, 118, 62, 143, 121, 48, 110, 175, 112, 218, 245, 65];
+        let encrypted_time = encrypt(&secret, &vec![0u8; 8]);
+        let time = FixedOffset::east(7200).ymd(2021, 5, 24).and_hms(13, 0, 0);
+
+        let updated_encrypted_time = update_time(&encrypted_time, &secret, time).unwrap();
+        let decrypted_time = decrypt(&secret, &updated_encrypted_time);
+
+        assert_eq!(Utc.ymd(2021, 5, 24).and_hms(11, 0, 0).timestamp().to_be_bytes()[4..], decrypted_time[0..4]);
+        assert_eq!(time.offset().local_minus_utc(), i32::from_be_bytes(decrypted_time[4..8].try_into().unwrap()));
+    }
+
+    #[test]
+    fn it_can_decode_clock_and_drift() {
+        let mut thermostat = create_thermostat();
+        let device_time = Utc.ymd(2021, 5, 24).and_hms(11, 0, 0);
+        thermostat.time = encrypt(&thermostat.secret, &TimeBlock { time: Some(device_time), utc_offset: 7200 }.encode());
+        thermostat.time_read_at = Some(device_time.timestamp() - 90);
+
+        assert_eq!(Some(Clock {
+            time: device_time,
+            utc_offset: 7200,
+            drift: Some(90),
+        }), ParsedThermostat::try_from(&thermostat).unwrap().clock);
+    }
+
+    #[test]
+    fn it_has_no_clock_when_time_was_not_read() {
+        assert_eq!(None, ParsedThermostat::try_from(&create_thermostat()).unwrap().clock);
+    }
+
     #[test]
     fn it_can_update_schedule_mode() {
         let secret = vec![215u8, 91, 125, 126, 14, 118, 62, 143, 121, 48, 110, 175, 112, 218, 245, 65];
@@ -300,7 +524,7 @@ mod tests {
         let old_decrypted_settings = decrypt(&secret, &encrypted_settings);
         assert_eq!(1, old_decrypted_settings[4]);
 
-        let updated_encrypted_settings = update_schedule_mode(&encrypted_settings, &secret, 3);
+        let updated_encrypted_settings = update_schedule_mode(&encrypted_settings, &secret, 3).unwrap();
         let decrypted_settings = decrypt(&secret, &updated_encrypted_settings);
 
         assert_eq!(3, decrypted_settings[4]);
@@ -320,22 +544,22 @@ mod tests {
 
     #[test]
     fn it_can_decrypt_and_decode_set_point_temperature() {
-        assert_eq!(Temperature::from_degrees_celcius(23.0), create_parsed_thermostat().set_point_temperature);
+        assert_eq!(Temperature::try_from(23.0).unwrap(), create_parsed_thermostat().set_point_temperature);
     }
 
     #[test]
     fn it_can_decrypt_and_decode_room_temperature() {
-        assert_eq!(Temperature::from_degrees_celcius(23.0), create_parsed_thermostat().room_temperature);
+        assert_eq!(Temperature::try_from(23.0).unwrap(), create_parsed_thermostat().room_temperature);
     }
 
     #[test]
     fn it_can_decrypt_and_decode_vacation_temperature() {
-        assert_eq!(Temperature::from_degrees_celcius(17.0), create_parsed_thermostat().vacation_temperature);
+        assert_eq!(Temperature::try_from(17.0).unwrap(), create_parsed_thermostat().vacation_temperature);
     }
 
     #[test]
     fn it_can_decrypt_and_decode_frost_protection_temperature() {
-        assert_eq!(Temperature::from_degrees_celcius(6.0), create_parsed_thermostat().frost_protection_temperature);
+        assert_eq!(Temperature::try_from(6.0).unwrap(), create_parsed_thermostat().frost_protection_temperature);
     }
 
     #[test]
@@ -526,22 +750,76 @@ mod tests {
             }, create_parsed_thermostat_with_schedule().schedule_sunday);
     }
 
-    fn create_parsed_thermostat() -> ParsedThermostat {
-        let thermostat = Thermostat {
-            serial: "0:04:2F:06:24:D1".to_string(),
-            secret: vec![215u8, 91, 125, 126, 14, 118, 62, 143, 121, 48, 110, 175, 112, 218, 245, 65],
-            name: vec![177u8, 174, 159, 196, 58, 140, 76, 22, 18, 192, 117, 144, 240, 100, 45, 250],
-            battery_level: vec![78u8],
-            temperature: vec![7u8, 148, 108, 151, 150, 177, 75, 43],
-            settings: vec![23u8, 243, 171, 192, 165, 81, 175, 118, 209, 79, 41, 151, 155, 212, 21, 255],
-            schedule_1: vec![10u8, 152, 79, 196, 233, 136, 156, 34, 203, 230, 55, 201, 151, 192, 235, 253, 190, 155, 204, 38],
-            schedule_2: vec![197u8, 163, 198, 34, 14, 212, 18, 186, 82, 212, 133, 156],
-            schedule_3: vec![197u8, 163, 198, 34, 14, 212, 18, 186, 82, 212, 133, 156],
+    #[test]
+    fn it_reports_characteristic_and_offset_of_truncated_data() {
+        let mut thermostat = create_thermostat();
+        thermostat.schedule_2 = thermostat.schedule_2[..8].to_vec();
 
-            ..Default::default()
-        };
+        let error = ParsedThermostat::try_from(&thermostat).err().unwrap();
 
-        ParsedThermostat::from_thermostat(&thermostat)
+        assert_eq!("schedule 2", error.characteristic);
+        assert_eq!(8, error.offset);
+    }
+
+    #[test]
+    fn it_does_not_panic_on_data_that_cannot_be_decrypted() {
+        let mut thermostat = create_thermostat();
+        thermostat.name = vec![177u8, 174, 159];
+
+        assert_eq!(ParseError {
+            characteristic: "name",
+            offset: 3,
+            reason: "Expected 16 bytes of name, got 3".to_string(),
+        }, ParsedThermostat::try_from(&thermostat).err().unwrap());
+    }
+
+    #[test]
+    fn it_reports_missing_battery_level() {
+        let mut thermostat = create_thermostat();
+        thermostat.battery_level = vec![];
+
+        let error = ParsedThermostat::try_from(&thermostat).err().unwrap();
+
+        assert_eq!("battery level", error.characteristic);
+        assert_eq!(0, error.offset);
+    }
+
+    #[test]
+    fn it_keeps_unknown_schedule_mode() {
+        let mut thermostat = create_thermostat();
+        thermostat.settings = update_schedule_mode(&thermostat.settings, &thermostat.secret, 2).unwrap();
+
+        assert_eq!(ScheduleMode::Unknown(2), ParsedThermostat::try_from(&thermostat).unwrap().schedule_mode);
+    }
+
+    #[test]
+    fn it_converts_schedule_mode_to_and_from_byte() {
+        for byte in 0..=255u8 {
+            assert_eq!(byte, ScheduleMode::from_byte(byte).to_byte());
+        }
+        assert_eq!(ScheduleMode::Vacation, ScheduleMode::from_byte(3));
+        assert_eq!("Unknown (2)", ScheduleMode::from_byte(2).to_string());
+    }
+
+    #[test]
+    fn it_can_parse_schedule_mode_names_and_values() {
+        assert_eq!(Some(ScheduleMode::Scheduled), ScheduleMode::parse("scheduled"));
+        assert_eq!(Some(ScheduleMode::Manual), ScheduleMode::parse("0"));
+        assert_eq!(Some(ScheduleMode::Unknown(2)), ScheduleMode::parse("2"));
+        assert_eq!(None, ScheduleMode::parse("8"));
+        assert_eq!(None, ScheduleMode::parse("256"));
+        assert_eq!(None, ScheduleMode::parse("hold"));
+    }
+
+    #[test]
+    fn it_fails_to_update_data_that_cannot_be_decrypted() {
+        let secret = vec![215u8, 91, 125, 126, 14, 118, 62, 143, 121, 48, 110, 175, 112, 218, 245, 65];
+
+        assert!(update_schedule_mode(&vec![1u8, 2, 3], &secret, 3).is_err());
+    }
+
+    fn create_parsed_thermostat() -> ParsedThermostat {
+        ParsedThermostat::try_from(&create_thermostat()).unwrap()
     }
 
     fn create_parsed_thermostat_with_schedule() -> ParsedThermostat {
@@ -559,7 +837,7 @@ mod tests {
             ..Default::default()
         };
 
-        ParsedThermostat::from_thermostat(&thermostat)
+        ParsedThermostat::try_from(&thermostat).unwrap()
     }
 
     fn create_parsed_thermostat_with_vacation_schedule() -> ParsedThermostat {
@@ -577,7 +855,7 @@ mod tests {
             ..Default::default()
         };
 
-        ParsedThermostat::from_thermostat(&thermostat)
+        ParsedThermostat::try_from(&thermostat).unwrap()
     }
 
 
@@ -596,6 +874,6 @@ mod tests {
             ..Default::default()
         };
 
-        ParsedThermostat::from_thermostat(&thermostat)
+        ParsedThermostat::try_from(&thermostat).unwrap()
     }
 }
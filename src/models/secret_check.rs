@@ -0,0 +1,154 @@
+use std::fmt;
+
+use crate::encryption::{can_decrypt, decrypt};
+use crate::models::parsed_thermostat::ScheduleMode;
+use crate::models::thermostats::Thermostat;
+
+// 40°C. Anything above this is not a temperature the thermostat would report.
+const MAX_PLAUSIBLE_TEMPERATURE: u8 = 80;
+
+/// The stored secret key no longer decrypts the data from the thermostat
+/// into something sensible. This typically happens when the thermostat has
+/// been reset and paired with the app again.
+#[derive(Debug, PartialEq)]
+pub struct InvalidSecret {
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidSecret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The secret key does not seem to match the thermostat ({})", self.reason)
+    }
+}
+
+/// Values that cannot be decrypted at all say nothing about the secret, so
+/// they are left for the parser to report.
+pub fn check_secret(thermostat: &Thermostat) -> Result<(), InvalidSecret> {
+    check_name(&thermostat.secret, &thermostat.name)?;
+    check_temperature(&thermostat.secret, &thermostat.temperature)?;
+    check_settings(&thermostat.secret, &thermostat.settings)
+}
+
+pub fn check_name(secret: &Vec<u8>, encrypted_name: &Vec<u8>) -> Result<(), InvalidSecret> {
+    if !can_decrypt(encrypted_name) {
+        return Ok(());
+    }
+    let decrypted_name = decrypt(secret, encrypted_name);
+
+    // The name is padded with zeros at the end
+    let name_length = decrypted_name.iter().position(|byte| *byte == 0).unwrap_or(decrypted_name.len());
+    if decrypted_name[name_length..].iter().any(|byte| *byte != 0) {
+        return invalid("name is not zero-terminated");
+    }
+    // Names are UTF-8, and often have letters like æ, ø and å
+    match std::str::from_utf8(&decrypted_name[..name_length]) {
+        Ok(name) if !name.chars().any(char::is_control) => Ok(()),
+        _ => invalid("name is not printable"),
+    }
+}
+
+pub fn check_temperature(secret: &Vec<u8>, encrypted_temperature: &Vec<u8>) -> Result<(), InvalidSecret> {
+    if !can_decrypt(encrypted_temperature) {
+        return Ok(());
+    }
+    let decrypted_temperature = decrypt(secret, encrypted_temperature);
+    check_temperature_byte("set-point temperature", decrypted_temperature.first())?;
+    check_temperature_byte("room temperature", decrypted_temperature.get(1))
+}
+
+pub fn check_settings(secret: &Vec<u8>, encrypted_settings: &Vec<u8>) -> Result<(), InvalidSecret> {
+    if !can_decrypt(encrypted_settings) {
+        return Ok(());
+    }
+    let decrypted_settings = decrypt(secret, encrypted_settings);
+    check_temperature_byte("minimum temperature", decrypted_settings.get(1))?;
+    check_temperature_byte("maximum temperature", decrypted_settings.get(2))?;
+    check_temperature_byte("frost protection temperature", decrypted_settings.get(3))?;
+    check_temperature_byte("vacation temperature", decrypted_settings.get(5))?;
+    match decrypted_settings.get(4) {
+        Some(schedule_mode) if *schedule_mode <= ScheduleMode::MAX_VALUE => Ok(()),
+        Some(schedule_mode) => invalid(&format!("implausible schedule mode {}", schedule_mode)),
+        None => invalid("schedule mode is missing"),
+    }
+}
+
+fn check_temperature_byte(description: &str, byte: Option<&u8>) -> Result<(), InvalidSecret> {
+    match byte {
+        Some(byte) if *byte <= MAX_PLAUSIBLE_TEMPERATURE => Ok(()),
+        Some(byte) => invalid(&format!("implausible {} {}°C", description, *byte as f32 / 2.0)),
+        None => invalid(&format!("{} is missing", description)),
+    }
+}
+
+fn invalid(reason: &str) -> Result<(), InvalidSecret> {
+    Err(InvalidSecret { reason: reason.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::create_thermostat;
+
+    #[test]
+    fn it_accepts_matching_secret() {
+        assert_eq!(Ok(()), check_secret(&create_thermostat()));
+    }
+
+    #[test]
+    fn it_rejects_secret_of_another_thermostat() {
+        let mut thermostat = create_thermostat();
+        thermostat.secret = vec![83u8, 17, 201, 9, 140, 250, 33, 71, 5, 199, 62, 128, 14, 230, 101, 77];
+
+        assert!(check_secret(&thermostat).is_err());
+    }
+
+    #[test]
+    fn it_leaves_values_that_cannot_be_decrypted_to_the_parser() {
+        let mut thermostat = create_thermostat();
+        thermostat.settings = vec![1u8, 2, 3];
+
+        assert_eq!(Ok(()), check_secret(&thermostat));
+    }
+
+    #[test]
+    fn it_rejects_unprintable_name() {
+        let secret = create_thermostat().secret;
+        let name = crate::encryption::encrypt(&secret, &vec![65u8, 66, 7, 0, 0, 0, 0, 0]);
+
+        assert_eq!(Err(InvalidSecret { reason: "name is not printable".to_string() }), check_name(&secret, &name));
+    }
+
+    #[test]
+    fn it_accepts_utf8_name() {
+        let secret = create_thermostat().secret;
+        let mut name_bytes = "Køkken".as_bytes().to_vec();
+        name_bytes.resize(16, 0);
+        let name = crate::encryption::encrypt(&secret, &name_bytes);
+
+        assert_eq!(Ok(()), check_name(&secret, &name));
+    }
+
+    #[test]
+    fn it_rejects_name_that_is_not_utf8() {
+        let secret = create_thermostat().secret;
+        let name = crate::encryption::encrypt(&secret, &vec![75u8, 0x98, 0x85, 0x86, 0, 0, 0, 0]);
+
+        assert_eq!(Err(InvalidSecret { reason: "name is not printable".to_string() }), check_name(&secret, &name));
+    }
+
+    #[test]
+    fn it_rejects_implausible_temperature() {
+        let secret = create_thermostat().secret;
+        let temperature = crate::encryption::encrypt(&secret, &vec![46u8, 200, 0, 0, 0, 0, 0, 0]);
+
+        assert_eq!(Err(InvalidSecret { reason: "implausible room temperature 100°C".to_string() }), check_temperature(&secret, &temperature));
+    }
+
+    #[test]
+    fn it_rejects_implausible_schedule_mode() {
+        let secret = create_thermostat().secret;
+        let settings = crate::encryption::encrypt(&secret, &vec![0u8, 0, 0, 12, 9, 34, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        assert_eq!(Err(InvalidSecret { reason: "implausible schedule mode 9".to_string() }), check_settings(&secret, &settings));
+    }
+}
@@ -0,0 +1,81 @@
+use crate::models::parsed_thermostat::Temperature;
+
+/// The unit temperatures are shown and entered in. The thermostats always
+/// store half degrees Celsius.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TemperatureUnit {
+    Celsius,
+    Fahrenheit,
+}
+
+impl Default for TemperatureUnit {
+    fn default() -> Self {
+        Self::Celsius
+    }
+}
+
+impl TemperatureUnit {
+    pub fn parse(unit: &str) -> Option<Self> {
+        match unit.to_lowercase().as_str() {
+            "celsius" | "c" => Some(Self::Celsius),
+            "fahrenheit" | "f" => Some(Self::Fahrenheit),
+            _ => None,
+        }
+    }
+
+    pub fn to_celsius(self, degrees: f32) -> f32 {
+        match self {
+            Self::Celsius => degrees,
+            Self::Fahrenheit => (degrees - 32.0) * 5.0 / 9.0,
+        }
+    }
+
+    pub fn to_unit(self, degrees_celsius: f32) -> f32 {
+        match self {
+            Self::Celsius => degrees_celsius,
+            Self::Fahrenheit => degrees_celsius * 9.0 / 5.0 + 32.0,
+        }
+    }
+
+    /// Formats degrees Celsius in this unit
+    pub fn format_degrees(self, degrees_celsius: f32) -> String {
+        match self {
+            Self::Celsius => format!("{}°C", degrees_celsius),
+            // Half degrees Celsius don't give whole degrees Fahrenheit
+            Self::Fahrenheit => format!("{}°F", (self.to_unit(degrees_celsius) * 10.0).round() / 10.0),
+        }
+    }
+
+    pub fn format(self, temperature: Temperature) -> String {
+        self.format_degrees(temperature.in_degrees_celcius())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_can_parse_units() {
+        assert_eq!(Some(TemperatureUnit::Celsius), TemperatureUnit::parse("celsius"));
+        assert_eq!(Some(TemperatureUnit::Fahrenheit), TemperatureUnit::parse("Fahrenheit"));
+        assert_eq!(Some(TemperatureUnit::Fahrenheit), TemperatureUnit::parse("F"));
+        assert_eq!(None, TemperatureUnit::parse("kelvin"));
+    }
+
+    #[test]
+    fn it_converts_fahrenheit_to_and_to_unit() {
+        assert_eq!(10.0, TemperatureUnit::Fahrenheit.to_celsius(50.0));
+        assert_eq!(212.0, TemperatureUnit::Fahrenheit.to_unit(100.0));
+        assert_eq!(21.5, TemperatureUnit::Celsius.to_celsius(21.5));
+    }
+
+    #[test]
+    fn it_formats_temperatures_in_unit() {
+        let temperature = Temperature::from_byte(43);
+
+        assert_eq!("21.5°C", TemperatureUnit::Celsius.format(temperature));
+        assert_eq!("70.7°F", TemperatureUnit::Fahrenheit.format(temperature));
+        assert_eq!("68°F", TemperatureUnit::Fahrenheit.format_degrees(20.0));
+    }
+}
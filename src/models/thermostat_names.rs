@@ -29,6 +29,15 @@ pub fn stripped_name(name: &String) -> String {
     parts.next().unwrap().to_string()
 }
 
+/// The advertised name starts with a single digit before the serial. We don't
+/// know what it means yet, but it's useful to show when scanning.
+pub fn leading_digit(name: &str) -> u8 {
+    match name.split(';').next().filter(|digit| is_single_digit(digit)) {
+        Some(digit) => digit.parse().unwrap(),
+        None => panic!("Not a thermostat name: {}", name),
+    }
+}
+
 fn is_single_digit(s: &str) -> bool {
     s.len() == 1 && s.chars().all(|c| c.is_ascii_digit())
 }
@@ -83,4 +92,10 @@ mod tests {
         assert_eq!("0:04:2F:06:24:D1", stripped_name(&"0;0:04:2F:06:24:D1;eTRV".to_string()));
     }
 
+    #[test]
+    fn it_can_give_leading_digit_of_thermostat_name() {
+        assert_eq!(0, leading_digit("0;0:04:2F:06:24:D1;eTRV"));
+        assert_eq!(4, leading_digit("4;0:04:2F:C0:F2:58;eTRV"));
+    }
+
 }
\ No newline at end of file
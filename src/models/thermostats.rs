@@ -17,9 +17,13 @@ impl Thermostats {
     }
 
     pub fn load() -> std::io::Result<Self> {
-        let file_path = Self::file_path()?;
-        if Path::new(&file_path[..]).exists() {
-            let serialized_thermostats = fs::read_to_string(&file_path[..])?;
+        Self::load_from(&Self::file_path()?)
+    }
+
+    /// Loads thermostats from another file, e.g. a copy of an earlier state
+    pub fn load_from(file_path: &str) -> std::io::Result<Self> {
+        if Path::new(file_path).exists() {
+            let serialized_thermostats = fs::read_to_string(file_path)?;
             Ok(serde_json::from_str(&serialized_thermostats[..])?)
         } else {
             Ok(Self {
@@ -76,16 +80,151 @@ pub struct Thermostat {
     pub schedule_2: Vec<u8>,
     pub schedule_3: Vec<u8>,
 
+    // Empty if read by an older version
+    #[serde(default)]
+    pub time: Vec<u8>,
+    // When the time was read, in seconds since epoch on this computer
+    #[serde(default)]
+    pub time_read_at: Option<i64>,
+
+    // Not encrypted, so these can be read without the secret
+    #[serde(default)]
+    pub device_information: DeviceInformation,
+
+    // Set when the secret no longer matches the thermostat, e.g. after a reset
+    #[serde(default)]
+    pub needs_repairing: bool,
+
+    // Upcoming vacation periods, ordered by start. The thermostat only stores
+    // one, so sync programs the next one once the current one has ended.
+    #[serde(default)]
+    pub queued_vacation_periods: Vec<(i64, i64)>,
+
     // New values that haven't yet been saved to the thermostat
     pub new_set_point_temperature: Option<f32>,
     pub new_vacation_period: Option<(i64, i64)>,
     pub new_schedule_mode: Option<u8>,
+    #[serde(default)]
+    pub new_device_settings: DeviceSettingsChanges,
+    // Set the clock of the thermostat to the time of this computer
+    #[serde(default)]
+    pub new_time: bool,
+}
+
+impl Thermostat {
+    /// Adds a vacation period to the queue, unless it overlaps one that is
+    /// already queued, in which case that one is returned
+    pub fn queue_vacation_period(&mut self, start: i64, end: i64) -> Result<(), (i64, i64)> {
+        if let Some(overlapping) = self.queued_vacation_periods.iter().find(|(queued_start, queued_end)| start < *queued_end && *queued_start < end) {
+            return Err(*overlapping);
+        }
+        let index = self.queued_vacation_periods.iter().position(|(queued_start, _)| *queued_start > start).unwrap_or(self.queued_vacation_periods.len());
+        self.queued_vacation_periods.insert(index, (start, end));
+        Ok(())
+    }
+
+    /// Moves the next queued vacation period to `new_vacation_period` if the
+    /// vacation period in the thermostat has ended at `now`, and no other
+    /// vacation period is waiting to be written. Queued periods that have
+    /// already ended are dropped.
+    pub fn take_next_vacation_period(&mut self, current_vacation_end: Option<i64>, now: i64) -> Option<(i64, i64)> {
+        self.queued_vacation_periods.retain(|(_, end)| *end > now);
+        if self.new_vacation_period.is_some() || current_vacation_end.map_or(false, |end| end > now) || self.queued_vacation_periods.is_empty() {
+            return None;
+        }
+        let next_vacation_period = self.queued_vacation_periods.remove(0);
+        self.new_vacation_period = Some(next_vacation_period);
+        Some(next_vacation_period)
+    }
+}
+
+/// New values for the temperature limits in the settings characteristic.
+/// `None` leaves the value on the thermostat as it is.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct DeviceSettingsChanges {
+    pub minimum_temperature: Option<f32>,
+    pub maximum_temperature: Option<f32>,
+}
+
+impl DeviceSettingsChanges {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The standard Bluetooth Device Information service. Each value is `None` if
+/// the thermostat doesn't provide it.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct DeviceInformation {
+    pub manufacturer_name: Option<String>,
+    pub model_number: Option<String>,
+    pub firmware_revision: Option<String>,
+    pub hardware_revision: Option<String>,
+    pub software_revision: Option<String>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn it_keeps_queued_vacation_periods_ordered_by_start() {
+        let mut thermostat = Thermostat::default();
+
+        thermostat.queue_vacation_period(300, 400).unwrap();
+        thermostat.queue_vacation_period(100, 200).unwrap();
+        thermostat.queue_vacation_period(200, 300).unwrap();
+
+        assert_eq!(vec![(100, 200), (200, 300), (300, 400)], thermostat.queued_vacation_periods);
+    }
+
+    #[test]
+    fn it_rejects_overlapping_vacation_periods() {
+        let mut thermostat = Thermostat::default();
+        thermostat.queue_vacation_period(100, 200).unwrap();
+
+        assert_eq!(Err((100, 200)), thermostat.queue_vacation_period(150, 250));
+        assert_eq!(Err((100, 200)), thermostat.queue_vacation_period(50, 300));
+        assert_eq!(vec![(100, 200)], thermostat.queued_vacation_periods);
+    }
+
+    #[test]
+    fn it_takes_the_next_vacation_period_when_the_current_one_has_ended() {
+        let mut thermostat = Thermostat {
+            queued_vacation_periods: vec![(100, 200), (300, 400), (500, 600)],
+            ..Default::default()
+        };
+
+        assert_eq!(None, thermostat.take_next_vacation_period(Some(250), 220));
+        assert_eq!(Some((300, 400)), thermostat.take_next_vacation_period(Some(250), 250));
+
+        assert_eq!(Some((300, 400)), thermostat.new_vacation_period);
+        assert_eq!(vec![(500, 600)], thermostat.queued_vacation_periods);
+    }
+
+    #[test]
+    fn it_takes_the_next_vacation_period_when_the_thermostat_has_none() {
+        let mut thermostat = Thermostat {
+            queued_vacation_periods: vec![(300, 400)],
+            ..Default::default()
+        };
+
+        assert_eq!(Some((300, 400)), thermostat.take_next_vacation_period(None, 100));
+        assert!(thermostat.queued_vacation_periods.is_empty());
+    }
+
+    #[test]
+    fn it_leaves_the_queue_while_another_vacation_period_is_pending() {
+        let mut thermostat = Thermostat {
+            queued_vacation_periods: vec![(300, 400)],
+            new_vacation_period: Some((0, 0)),
+            ..Default::default()
+        };
+
+        assert_eq!(None, thermostat.take_next_vacation_period(None, 100));
+        assert_eq!(vec![(300, 400)], thermostat.queued_vacation_periods);
+    }
+
     #[test]
     fn it_can_serialize_and_deserialize() -> std::io::Result<()> {
         let thermostats = create_test_data();
@@ -101,6 +240,17 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn it_can_deserialize_thermostat_saved_by_older_versions() -> std::io::Result<()> {
+        let serialized_thermostat = r#"{"serial":"12345","secret":[1],"name":[2],"battery_level":[75],"temperature":[3],"settings":[4],"schedule_1":[5],"schedule_2":[6],"schedule_3":[7],"new_set_point_temperature":null,"new_vacation_period":null,"new_schedule_mode":null}"#;
+
+        let deserialized_thermostat: Thermostat = serde_json::from_str(serialized_thermostat)?;
+        assert!(!deserialized_thermostat.needs_repairing);
+        assert_eq!(DeviceInformation::default(), deserialized_thermostat.device_information);
+
+        Ok(())
+    }
+
     #[test]
     fn it_can_save_and_load() -> std::io::Result<()> {
         let thermostats = create_test_data();
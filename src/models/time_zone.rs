@@ -0,0 +1,104 @@
+use chrono::prelude::*;
+use chrono_tz::Tz;
+
+/// The time zone times are entered and shown in.
+///
+/// The thermostat itself keeps time in UTC: the start and end of a vacation,
+/// and the time of its clock, are seconds since the Unix epoch. Only the
+/// clock has a UTC offset next to it. So converting to and from local time
+/// is up to us, and must use the time zone rules for the time being
+/// converted, not the UTC offset of today.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeZoneSetting {
+    /// The time zone of this computer
+    Local,
+    Named(Tz),
+}
+
+impl Default for TimeZoneSetting {
+    fn default() -> Self {
+        Self::Local
+    }
+}
+
+impl TimeZoneSetting {
+    /// Either `local`, or a name from the time zone database, like Europe/Copenhagen
+    pub fn parse(name: &str) -> Option<Self> {
+        if name == "local" {
+            Some(Self::Local)
+        } else {
+            name.parse::<Tz>().ok().map(Self::Named)
+        }
+    }
+
+    pub fn format(&self, time: DateTime<Utc>) -> String {
+        match self {
+            Self::Local => time.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string(),
+            Self::Named(tz) => time.with_timezone(tz).format("%Y-%m-%d %H:%M").to_string(),
+        }
+    }
+
+    /// A time on the clock in this time zone. In the repeated hour when the
+    /// clocks are put back, the first occurrence is used. Times skipped when
+    /// the clocks are put forward don't exist.
+    pub fn utc_from_local(&self, time: &NaiveDateTime) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Local => Local.from_local_datetime(time).earliest().map(|time| time.with_timezone(&Utc)),
+            Self::Named(tz) => tz.from_local_datetime(time).earliest().map(|time| time.with_timezone(&Utc)),
+        }
+    }
+
+    /// The time, with the UTC offset in effect at that time
+    pub fn with_offset(&self, time: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self {
+            Self::Local => time.with_timezone(&Local).into(),
+            Self::Named(tz) => {
+                let offset = tz.offset_from_utc_datetime(&time.naive_utc()).fix();
+                time.with_timezone(&offset)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn copenhagen() -> TimeZoneSetting {
+        TimeZoneSetting::parse("Europe/Copenhagen").unwrap()
+    }
+
+    #[test]
+    fn it_can_parse_time_zones() {
+        assert_eq!(Some(TimeZoneSetting::Local), TimeZoneSetting::parse("local"));
+        assert_eq!(Some(TimeZoneSetting::Named(chrono_tz::America::New_York)), TimeZoneSetting::parse("America/New_York"));
+        assert_eq!(None, TimeZoneSetting::parse("Europe/Atlantis"));
+    }
+
+    #[test]
+    fn it_formats_times_with_the_offset_in_effect_at_that_time() {
+        // Daylight saving time ends 2026-10-25 03:00 in Copenhagen
+        assert_eq!("2026-10-24 12:00", copenhagen().format(Utc.ymd(2026, 10, 24).and_hms(10, 0, 0)));
+        assert_eq!("2026-10-26 12:00", copenhagen().format(Utc.ymd(2026, 10, 26).and_hms(11, 0, 0)));
+    }
+
+    #[test]
+    fn it_formats_both_occurrences_of_the_repeated_hour() {
+        assert_eq!("2026-10-25 02:30", copenhagen().format(Utc.ymd(2026, 10, 25).and_hms(0, 30, 0)));
+        assert_eq!("2026-10-25 02:30", copenhagen().format(Utc.ymd(2026, 10, 25).and_hms(1, 30, 0)));
+    }
+
+    #[test]
+    fn it_converts_clock_times_to_utc() {
+        let time = |hour, minute| NaiveDate::from_ymd(2026, 10, 25).and_hms(hour, minute, 0);
+        assert_eq!(Some(Utc.ymd(2026, 10, 24).and_hms(22, 0, 0)), copenhagen().utc_from_local(&time(0, 0)));
+        assert_eq!(Some(Utc.ymd(2026, 10, 25).and_hms(0, 30, 0)), copenhagen().utc_from_local(&time(2, 30)));
+        assert_eq!(None, copenhagen().utc_from_local(&NaiveDate::from_ymd(2026, 3, 29).and_hms(2, 30, 0)));
+    }
+
+    #[test]
+    fn it_knows_the_utc_offset_at_a_given_time() {
+        assert_eq!(7200, copenhagen().with_offset(Utc.ymd(2026, 3, 29).and_hms(1, 0, 0)).offset().local_minus_utc());
+        assert_eq!(3600, copenhagen().with_offset(Utc.ymd(2026, 3, 29).and_hms(0, 59, 0)).offset().local_minus_utc());
+    }
+}
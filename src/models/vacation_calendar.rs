@@ -0,0 +1,257 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use chrono_tz::Tz;
+use ical::parser::ical::component::IcalEvent;
+use ical::property::Property;
+use ical::IcalParser;
+use std::fmt;
+use std::io::BufRead;
+
+use crate::models::time_zone::TimeZoneSetting;
+
+/// An event from an iCalendar file that is a vacation
+#[derive(Debug, PartialEq)]
+pub struct CalendarVacation {
+    pub summary: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// An event that matches, but can't be turned into a vacation
+#[derive(Debug, PartialEq)]
+pub struct InvalidEvent {
+    pub summary: String,
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Skipping '{}': {}", self.summary, self.reason)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct InvalidCalendar {
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidCalendar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Could not read the calendar: {}", self.reason)
+    }
+}
+
+/// Finds the events of an iCalendar file with `keyword` as one of their
+/// categories or in their summary, ignoring case. All-day events, and times
+/// without a time zone or with one that isn't in the time zone database, are
+/// in `time_zone`.
+pub fn find_vacations<B: BufRead>(reader: B, keyword: &str, time_zone: TimeZoneSetting) -> Result<Vec<Result<CalendarVacation, InvalidEvent>>, InvalidCalendar> {
+    let mut vacations = vec![];
+    for calendar in IcalParser::new(reader) {
+        let calendar = calendar.map_err(|error| InvalidCalendar { reason: error.to_string() })?;
+        for event in calendar.events.iter().filter(|event| matches(event, keyword)) {
+            let summary = summary(event);
+            vacations.push(vacation(event, time_zone).map_err(|reason| InvalidEvent { summary: summary.clone(), reason }).map(|(start, end)| CalendarVacation { summary, start, end }));
+        }
+    }
+    vacations.sort_by_key(|vacation| vacation.as_ref().map(|vacation| vacation.start).ok());
+    Ok(vacations)
+}
+
+fn matches(event: &IcalEvent, keyword: &str) -> bool {
+    let keyword = keyword.to_lowercase();
+    let in_categories = event.properties.iter()
+        .filter(|property| property.name == "CATEGORIES")
+        .filter_map(|property| property.value.as_ref())
+        .flat_map(|categories| categories.split(','))
+        .any(|category| category.trim().to_lowercase() == keyword);
+    in_categories || summary(event).to_lowercase().contains(&keyword)
+}
+
+fn summary(event: &IcalEvent) -> String {
+    match value(event, "SUMMARY") {
+        Some(summary) => summary.replace("\\,", ",").replace("\\;", ";").replace("\\n", " ").replace("\\\\", "\\"),
+        None => "(no summary)".to_string(),
+    }
+}
+
+fn property<'a>(event: &'a IcalEvent, name: &str) -> Option<&'a Property> {
+    event.properties.iter().find(|property| property.name == name)
+}
+
+fn value<'a>(event: &'a IcalEvent, name: &str) -> Option<&'a str> {
+    property(event, name).and_then(|property| property.value.as_deref())
+}
+
+fn parameter<'a>(property: &'a Property, name: &str) -> Option<&'a str> {
+    property.params.as_ref()?.iter()
+        .find(|(parameter_name, _)| parameter_name == name)
+        .and_then(|(_, values)| values.first())
+        .map(|value| value.as_str())
+}
+
+// The start and end of an event. The end of all-day events is the day after
+// the last day.
+fn vacation(event: &IcalEvent, time_zone: TimeZoneSetting) -> Result<(DateTime<Utc>, DateTime<Utc>), String> {
+    if property(event, "RRULE").is_some() {
+        return Err("repeating events are not supported".to_string());
+    }
+    let start_property = property(event, "DTSTART").ok_or_else(|| "no start".to_string())?;
+    let (start, all_day) = event_time(start_property, time_zone)?;
+    let end = if let Some(end_property) = property(event, "DTEND") {
+        event_time(end_property, time_zone)?.0
+    } else if let Some(duration) = value(event, "DURATION") {
+        start + parse_duration(duration).ok_or_else(|| format!("could not parse duration {}", duration))?
+    } else if all_day {
+        start + Duration::days(1)
+    } else {
+        return Err("no end".to_string());
+    };
+    if end <= start {
+        return Err("it ends before it starts".to_string());
+    }
+    Ok((start, end))
+}
+
+// The time, and whether it is a date for an all-day event
+fn event_time(property: &Property, time_zone: TimeZoneSetting) -> Result<(DateTime<Utc>, bool), String> {
+    let value = property.value.as_deref().unwrap_or("").trim();
+    let time_zone = match parameter(property, "TZID").and_then(|name| name.parse::<Tz>().ok()) {
+        Some(tz) => TimeZoneSetting::Named(tz),
+        None => time_zone,
+    };
+
+    if parameter(property, "VALUE") == Some("DATE") || value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").map_err(|_| format!("could not parse date {}", value))?;
+        let time = time_zone.utc_from_local(&date.and_hms(0, 0, 0)).ok_or_else(|| format!("{} has no midnight in the time zone", value))?;
+        return Ok((time, true));
+    }
+    if let Some(utc_value) = value.strip_suffix('Z') {
+        let time = NaiveDateTime::parse_from_str(utc_value, "%Y%m%dT%H%M%S").map_err(|_| format!("could not parse time {}", value))?;
+        return Ok((Utc.from_utc_datetime(&time), false));
+    }
+    let time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").map_err(|_| format!("could not parse time {}", value))?;
+    let time = time_zone.utc_from_local(&time).ok_or_else(|| format!("{} does not exist in the time zone, as the clocks are put forward", value))?;
+    Ok((time, false))
+}
+
+// Like P2W, P3D, PT12H, or P1DT2H30M
+fn parse_duration(duration: &str) -> Option<Duration> {
+    let duration = duration.trim().strip_prefix('+').unwrap_or(duration.trim());
+    let mut remaining = duration.strip_prefix('P')?;
+    let mut total = Duration::zero();
+    let mut in_time = false;
+    while !remaining.is_empty() {
+        if let Some(rest) = remaining.strip_prefix('T') {
+            in_time = true;
+            remaining = rest;
+            continue;
+        }
+        let unit_start = remaining.find(|character: char| !character.is_ascii_digit())?;
+        let amount = remaining[..unit_start].parse::<i64>().ok()?;
+        // The unit may be any character in a broken file, not just a one-byte one
+        let unit = remaining[unit_start..].chars().next()?;
+        total = total + match (unit, in_time) {
+            ('W', false) => Duration::weeks(amount),
+            ('D', false) => Duration::days(amount),
+            ('H', true) => Duration::hours(amount),
+            ('M', true) => Duration::minutes(amount),
+            ('S', true) => Duration::seconds(amount),
+            _ => return None,
+        };
+        remaining = &remaining[unit_start + unit.len_utf8()..];
+    }
+    Some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn copenhagen() -> TimeZoneSetting {
+        TimeZoneSetting::Named(chrono_tz::Europe::Copenhagen)
+    }
+
+    fn calendar(events: &str) -> String {
+        format!("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Test//EN\r\n{}END:VCALENDAR\r\n", events)
+    }
+
+    fn vacations(events: &str, keyword: &str) -> Vec<Result<CalendarVacation, InvalidEvent>> {
+        find_vacations(calendar(events).as_bytes(), keyword, copenhagen()).unwrap()
+    }
+
+    #[test]
+    fn it_finds_events_by_category_or_summary() {
+        let events = "BEGIN:VEVENT\r\nUID:1\r\nSUMMARY:Skiing\r\nCATEGORIES:Family,Vacation\r\nDTSTART;VALUE=DATE:20270213\r\nDTEND;VALUE=DATE:20270220\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:2\r\nSUMMARY:Dentist\r\nDTSTART:20261105T100000Z\r\nDTEND:20261105T110000Z\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:3\r\nSUMMARY:Autumn vacation\\, Rome\r\nDTSTART:20261020T060000Z\r\nDTEND:20261025T180000Z\r\nEND:VEVENT\r\n";
+
+        let expected_vacations = vec![
+            Ok(CalendarVacation {
+                summary: "Autumn vacation, Rome".to_string(),
+                start: Utc.ymd(2026, 10, 20).and_hms(6, 0, 0),
+                end: Utc.ymd(2026, 10, 25).and_hms(18, 0, 0),
+            }),
+            Ok(CalendarVacation {
+                summary: "Skiing".to_string(),
+                start: Utc.ymd(2027, 2, 12).and_hms(23, 0, 0),
+                end: Utc.ymd(2027, 2, 19).and_hms(23, 0, 0),
+            }),
+        ];
+        assert_eq!(expected_vacations, vacations(events, "VACATION"));
+    }
+
+    #[test]
+    fn it_uses_the_time_zone_of_the_event() {
+        let events = "BEGIN:VEVENT\r\nUID:1\r\nSUMMARY:Vacation\r\nDTSTART;TZID=America/New_York:20261024T120000\r\nDTEND;TZID=America/New_York:20261026T120000\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:2\r\nSUMMARY:Vacation\r\nDTSTART:20261224T120000\r\nDURATION:P1DT6H\r\nEND:VEVENT\r\n";
+
+        let vacations = vacations(events, "vacation");
+
+        assert_eq!(Utc.ymd(2026, 10, 24).and_hms(16, 0, 0), vacations[0].as_ref().unwrap().start);
+        assert_eq!(Utc.ymd(2026, 10, 26).and_hms(16, 0, 0), vacations[0].as_ref().unwrap().end);
+        assert_eq!(Utc.ymd(2026, 12, 24).and_hms(11, 0, 0), vacations[1].as_ref().unwrap().start);
+        assert_eq!(Utc.ymd(2026, 12, 25).and_hms(17, 0, 0), vacations[1].as_ref().unwrap().end);
+    }
+
+    #[test]
+    fn it_uses_the_time_zone_rules_for_each_day() {
+        // Daylight saving time ends 2026-10-25 in Copenhagen
+        let events = "BEGIN:VEVENT\r\nUID:1\r\nSUMMARY:Vacation\r\nDTSTART;VALUE=DATE:20261024\r\nDTEND;VALUE=DATE:20261026\r\nEND:VEVENT\r\n";
+
+        let vacation = vacations(events, "vacation").remove(0).unwrap();
+
+        assert_eq!(Utc.ymd(2026, 10, 23).and_hms(22, 0, 0), vacation.start);
+        assert_eq!(Utc.ymd(2026, 10, 25).and_hms(23, 0, 0), vacation.end);
+    }
+
+    #[test]
+    fn it_reports_events_it_cannot_use() {
+        let events = "BEGIN:VEVENT\r\nUID:1\r\nSUMMARY:Weekly vacation\r\nRRULE:FREQ=WEEKLY\r\nDTSTART:20261024T120000Z\r\nDTEND:20261024T180000Z\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:2\r\nSUMMARY:Vacation\r\nDTSTART:20261024T120000Z\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:3\r\nSUMMARY:Ferie vacation\r\nDTSTART:20261024T120000Z\r\nDURATION:P1é\r\nEND:VEVENT\r\n";
+
+        let expected_errors = vec![
+            Err(InvalidEvent { summary: "Weekly vacation".to_string(), reason: "repeating events are not supported".to_string() }),
+            Err(InvalidEvent { summary: "Vacation".to_string(), reason: "no end".to_string() }),
+            Err(InvalidEvent { summary: "Ferie vacation".to_string(), reason: "could not parse duration P1é".to_string() }),
+        ];
+        assert_eq!(expected_errors, vacations(events, "vacation"));
+    }
+
+    #[test]
+    fn it_rejects_files_that_are_not_calendars() {
+        assert!(find_vacations("Not a calendar\r\n".as_bytes(), "vacation", copenhagen()).is_err());
+    }
+
+    #[test]
+    fn it_can_parse_durations() {
+        assert_eq!(Some(Duration::weeks(2)), parse_duration("P2W"));
+        assert_eq!(Some(Duration::hours(12)), parse_duration("PT12H"));
+        assert_eq!(Some(Duration::days(1) + Duration::hours(2) + Duration::minutes(30)), parse_duration("P1DT2H30M"));
+        assert_eq!(None, parse_duration("2 days"));
+        assert_eq!(None, parse_duration("P2H"));
+        assert_eq!(None, parse_duration("P1é"));
+        assert_eq!(None, parse_duration("PT1Hé"));
+    }
+}
@@ -0,0 +1,31 @@
+use crate::models::thermostats::Thermostat;
+
+pub fn arguments(arguments: &[&str]) -> Vec<String> {
+    arguments.iter().map(|argument| argument.to_string()).collect()
+}
+
+/// The thermostat named "Alrum opgang", as read before any schedule or
+/// vacation was set.
+pub fn create_thermostat() -> Thermostat {
+    Thermostat {
+        serial: "0:04:2F:06:24:D1".to_string(),
+        secret: vec![215u8, 91, 125, 126, 14, 118, 62, 143, 121, 48, 110, 175, 112, 218, 245, 65],
+        name: vec![177u8, 174, 159, 196, 58, 140, 76, 22, 18, 192, 117, 144, 240, 100, 45, 250],
+        battery_level: vec![78u8],
+        temperature: vec![7u8, 148, 108, 151, 150, 177, 75, 43],
+        settings: vec![23u8, 243, 171, 192, 165, 81, 175, 118, 209, 79, 41, 151, 155, 212, 21, 255],
+        schedule_1: vec![10u8, 152, 79, 196, 233, 136, 156, 34, 203, 230, 55, 201, 151, 192, 235, 253, 190, 155, 204, 38],
+        schedule_2: vec![197u8, 163, 198, 34, 14, 212, 18, 186, 82, 212, 133, 156],
+        schedule_3: vec![197u8, 163, 198, 34, 14, 212, 18, 186, 82, 212, 133, 156],
+
+        ..Default::default()
+    }
+}
+
+/// The same thermostat with its schedule mode set to scheduled
+pub fn create_scheduled_thermostat() -> Thermostat {
+    Thermostat {
+        settings: vec![180u8, 249, 230, 196, 18, 146, 189, 34, 145, 102, 24, 26, 151, 111, 192, 189],
+        ..create_thermostat()
+    }
+}